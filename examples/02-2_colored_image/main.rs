@@ -7,7 +7,6 @@ use amethyst::{
     },
     renderer::{
         DisplayConfig, Pipeline, Stage, DrawFlat2D, ColorMask, ALPHA, DepthMode,
-        Camera, Projection,
         Texture, PngFormat, TextureMetadata,
         RenderBundle,
         Rgba
@@ -22,12 +21,14 @@ use amethyst::{
     },
 };
 
+use amethyst_test::camera::CameraBuilder;
+
 struct ExampleState;
 
 impl SimpleState for ExampleState {
     fn on_start(&mut self, data: StateData<'_, GameData<'_, '_>>) {
         let world = data.world;
-        initialise_camera(world);
+        CameraBuilder::new(500.0, 500.0).build(world);
         initialise_image(world);
     }
 }
@@ -76,18 +77,6 @@ fn main() -> amethyst::Result<()> {
     Ok(())
 }
 
-pub fn initialise_camera(world: &mut World) {
-    let mut transform = Transform::default();
-        transform.set_xyz(250.0, 250.0, 0.0);
-    world
-        .create_entity()
-        .with(Camera::from(Projection::orthographic(
-            -250.0, 250.0, -250.0, 250.0
-        )))
-        .with(transform)
-        .build();
-}
-
 fn initialise_image(world: &mut World) {
     let texture_handle = {
         let loader = world.read_resource::<Loader>();
@@ -7,7 +7,6 @@ use amethyst::{
     },
     renderer::{
         Pipeline, Stage, DrawFlat2D, ColorMask, ALPHA, DepthMode, DisplayConfig, RenderBundle,
-        Camera, Projection,
         Texture, PngFormat, TextureMetadata,
         SpriteSheet, SpriteSheetFormat, SpriteRender
     },
@@ -27,6 +26,8 @@ use amethyst::{
     }
 };
 
+use amethyst_test::camera::CameraBuilder;
+
 struct Icon;
 
 impl Component for Icon {
@@ -38,7 +39,7 @@ struct ExampleState;
 impl SimpleState for ExampleState {
     fn on_start(&mut self, data: StateData<'_, GameData<'_, '_>>) {
         let world = data.world;
-        initialise_camera(world);
+        CameraBuilder::new(500.0, 500.0).build(world);
         world.register::<Icon>();
         initialise_icon(world);
     }
@@ -111,17 +112,6 @@ fn main() -> amethyst::Result<()> {
     Ok(())
 }
 
-fn initialise_camera(world: &mut World) {
-    let mut transform = Transform::default();
-        transform.set_xyz(250.0, 250.0, 1.0);
-    world.create_entity()
-        .with(Camera::from(Projection::orthographic(
-            -250.0, 250.0, -250.0, 250.0
-        )))
-        .with(transform)
-        .build();
-}
-
 fn initialise_icon(world: &mut World) {
     let sprite_sheet_handle = {
         let loader = world.read_resource::<Loader>();
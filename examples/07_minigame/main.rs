@@ -11,9 +11,12 @@ use amethyst::{
         SpriteRender
     },
     input::{
-        InputBundle, InputHandler,
+        InputBundle, InputHandler, StringBindings, ControllerButton,
         is_key_down,
     },
+    ui::{
+        DrawUi, UiBundle
+    },
     utils::application_root_dir,
     ecs::prelude::{
         Component, DenseVecStorage, Entity,
@@ -24,8 +27,12 @@ use amethyst::{
 
 use amethyst_test::{
     TransformExt,
-    initialise_camera,
-    load_sprite_sheet
+    load_sprite_sheet,
+    gamepad::{Gamepads, GamepadBundle, Stick},
+    camera::{
+        CameraBuilder, CameraFollow, CameraFollowBundle,
+        ScalePolicy, VirtualResolutionBundle
+    },
 };
 
 use std::path::PathBuf;
@@ -56,8 +63,17 @@ struct ExampleState;
 impl SimpleState for ExampleState {
     fn on_start(&mut self, data: StateData<'_, GameData<'_, '_>>) {
         let world = data.world;
-        initialise_camera(world, [500.0, 500.0]);
         initialise_player(world);
+        let player = world.read_resource::<PlayerEntity>().0;
+        let camera = CameraBuilder::new(500.0, 500.0)
+            .with_scale_policy(ScalePolicy::Letterbox)
+            .build(world);
+        world.write_storage::<CameraFollow>()
+            .insert(camera, CameraFollow::new(player)
+                .with_smoothing(5.0)
+                .with_dead_zone(80.0, 60.0)
+                .with_look_ahead(0.2))
+            .expect("camera is alive");
     }
 
     fn handle_event(
@@ -105,23 +121,43 @@ impl<'s> System<'s> for PlayerMoveSystem {
         WriteStorage<'s, Player>,
         WriteStorage<'s, Transform>,
         ReadExpect<'s, PlayerEntity>,
-        Read<'s, InputHandler<String, String>>
+        Read<'s, InputHandler<StringBindings>>,
+        Read<'s, Gamepads>
     );
 
-    fn run(&mut self, (mut players, mut transforms, player, input): Self::SystemData) {
-        let dx = input.axis_value("x_axis").unwrap() as f32;
-        let dy = input.axis_value("y_axis").unwrap() as f32;
+    fn run(&mut self, (mut players, mut transforms, player, input, gamepads): Self::SystemData) {
+        let mut dx = input.axis_value("x_axis").unwrap() as f32;
+        let mut dy = input.axis_value("y_axis").unwrap() as f32;
+        // キーボードの入力がなければパッドのスティックか十字キーを使う
+        if let Some(pad) = gamepads.first() {
+            let (sx, sy) = pad.stick(Stick::Left);
+            let (px, py) = (
+                pad.get(ControllerButton::DPadRight) as i32 - pad.get(ControllerButton::DPadLeft) as i32,
+                pad.get(ControllerButton::DPadUp) as i32 - pad.get(ControllerButton::DPadDown) as i32,
+            );
+            if dx == 0.0 && dy == 0.0 {
+                if px != 0 || py != 0 {
+                    dx = px as f32;
+                    dy = py as f32;
+                } else {
+                    // スティックは下が正なので y を反転する
+                    dx = sx;
+                    dy = -sy;
+                }
+            }
+        }
         let speed = 3.0;
         if let Some(transform) = transforms.get_mut(player.0) {
             transform.translate_xyz(dx * speed, dy * speed, 0.0);
         }
 
         if let Some(player) = players.get_mut(player.0) {
-            player.0 = match dx as i32 {
-                 0 => State::Idle,
-                 1 => State::Right,
-                -1 => State::Left,
-                _ => panic!(),
+            player.0 = if dx > 0.0 {
+                State::Right
+            } else if dx < 0.0 {
+                State::Left
+            } else {
+                State::Idle
             };
         }
     }
@@ -140,21 +176,29 @@ fn main() -> amethyst::Result<()> {
                 ALPHA,
                 Some(DepthMode::LessEqualWrite)
             ))
+            // Letterbox の帯を描く
+            .with_pass(DrawUi::new())
     );
     let config = DisplayConfig::load(app_root.join("config.ron"));
     let render_bundle = RenderBundle::new(pipe, Some(config));
 
     let transform_bundle = TransformBundle::new();
 
-    let input_bundle = InputBundle::<String, String>::new()
+    // sdl_controller フィーチャーが有効なら SdlEventsSystem も一緒に追加され、パッドの入力が届く
+    let input_bundle = InputBundle::<StringBindings>::new()
         .with_bindings_from_file(app_root.join("bindings.ron"))?;
 
     let game_data = GameDataBuilder::new()
         .with_bundle(render_bundle.with_sprite_sheet_processor())?
-        .with_bundle(transform_bundle)?
         .with_bundle(input_bundle)?
+        .with_bundle(GamepadBundle::<StringBindings>::new())?
         .with(PlayerSpriteSystem(0), "player_sprite_system", &[])
-        .with(PlayerMoveSystem, "player-move-system", &[]);
+        .with(PlayerMoveSystem, "player-move-system", &["gamepad_system"])
+        .with_bundle(CameraFollowBundle::new().with_dep(&["player-move-system"]))?
+        // カメラを動かし終えてから GlobalTransform を計算する
+        .with_bundle(transform_bundle)?
+        .with_bundle(VirtualResolutionBundle::new())?
+        .with_bundle(UiBundle::<StringBindings>::new())?;
 
     Application::new(app_root, ExampleState, game_data)?.run();
 
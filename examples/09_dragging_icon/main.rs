@@ -1,10 +1,5 @@
 use amethyst::{
     prelude::*,
-    ecs::prelude::{
-        System, Component, DenseVecStorage,
-        Read, WriteStorage, ReadStorage,
-        Join
-    },
     core::transform::{
         Transform, TransformBundle
     },
@@ -14,35 +9,29 @@ use amethyst::{
         SpriteRender
     },
     input::{
-        is_key_down, Bindings, InputBundle
-    },
-    winit::{
-        VirtualKeyCode
+        is_key_down, Bindings, InputBundle, StringBindings
     },
+    winit::VirtualKeyCode,
 };
 
 use amethyst_test::{
     initialise_camera,
     load_sprite_sheet,
     mouse::*,
+    drag::{
+        Draggable, DragBundle, HitShape
+    },
 };
 
-struct Icon;
-
-impl Component for Icon {
-    type Storage = DenseVecStorage<Self>;
-}
-
 struct ExampleState;
 
 impl SimpleState for ExampleState {
     fn on_start(&mut self, data: StateData<'_, GameData<'_, '_>>) {
         let world = data.world;
 
-        initialise_camera(world);
-        world.add_resource(Mouse::new());
+        initialise_camera(world, [500.0, 500.0]);
+        initialise_mouse(world);
 
-        world.register::<Icon>();
         initialise_icon(world);
     }
 
@@ -60,50 +49,6 @@ impl SimpleState for ExampleState {
     }
 }
 
-struct DragSystem;
-
-impl<'s> System<'s> for DragSystem {
-    type SystemData = (
-        WriteStorage<'s, Icon>,
-        ReadStorage<'s, Transform>,
-        Read<'s, Mouse>
-    );
-
-    fn run(&mut self, (mut icons, transforms, mouse): Self::SystemData) {
-        if mouse.press {
-            for (icon, transform) in (&mut icons, &transforms).join() {
-                let translation = transform.translation();
-                let (x, y) = (translation.x, translation.y);
-                let d = dist(x, y, mouse.x, mouse.y);
-                println!("x: {}, y: {}, mx: {}, my: {}, dist: {}", x, y, mouse.x, mouse.y, d);
-                icon.0 = d <= 25.0;
-            }
-        } else {
-            for icon in (&mut icons).join() {
-                icon.0 = false;
-            }
-        }
-    }
-}
-
-struct MoveSystem;
-
-impl<'s> System<'s> for MoveSystem {
-    type SystemData = (
-        ReadStorage<'s, Icon>,
-        WriteStorage<'s, Transform>,
-        Read<'s, Mouse>,
-    );
-
-    fn run(&mut self, (icons, mut transforms, mouse): Self::SystemData) {
-        for (icon, transform) in (&icons, &mut transforms).join() {
-            if icon.0 {
-                transform.translate_xyz(mouse.mx, mouse.my, 0.0);
-            }
-        }
-    }
-}
-
 fn main() -> amethyst::Result<()> {
     amethyst::start_logger(Default::default());
 
@@ -126,11 +71,11 @@ fn main() -> amethyst::Result<()> {
 
     let game_data = GameDataBuilder::new()
         .with_bundle(render_bundle.with_sprite_sheet_processor())?
-        .with_bundle(transform_bundle)?
         .with_bundle(input_bundle)?
-        .with(MouseSystem, "mouse-system", &[])
-        .with(DragSystem, "drag-system", &[])
-        .with(MoveSystem, "move-system", &[]);
+        .with_bundle(MouseBundle::<StringBindings>::new())?
+        .with_bundle(DragBundle::<StringBindings>::new())?
+        // 動かし終えてから GlobalTransform を計算する
+        .with_bundle(transform_bundle)?;
 
     let mut game = Application::new(
         "./examples/09_dragging_icon/",
@@ -153,11 +98,8 @@ fn initialise_icon(world: &mut World) {
     world
         .create_entity()
         .with(sprite_render)
-        .with(Icon)
+        // アイコンの中心から半径 25px の範囲を掴める
+        .with(Draggable::new(HitShape::Circle(25.0)))
         .with(transform)
         .build();
 }
-
-fn dist(x0: f32, y0: f32, x1: f32, y1: f32) -> f32 {
-    (x1 - x0).hypot(y1 - y0)
-}
@@ -8,7 +8,7 @@ use amethyst::{
         DisplayConfig, DrawFlat2D, Pipeline, RenderBundle, Stage,
     },
     input::{
-        is_key_down, Bindings, InputBundle
+        is_key_down, Bindings, InputBundle, StringBindings
     },
     winit::{
         VirtualKeyCode, MouseButton
@@ -29,6 +29,9 @@ impl<'s> System<'s> for ClickSystem {
         if mouse.get_up(MouseButton::Left) {
             println!("release!");
         }
+        if mouse.get_click(MouseButton::Left, 2) {
+            println!("double click!");
+        }
     }
 }
 
@@ -37,7 +40,7 @@ struct ExampleState;
 impl SimpleState for ExampleState {
     fn on_start(&mut self, data: StateData<'_, GameData<'_, '_>>) {
         let world = data.world;
-        world.add_resource(Mouse::new());
+        initialise_mouse(world);
     }
 
     fn handle_event(
@@ -71,7 +74,7 @@ fn main() -> amethyst::Result<()> {
     let game_data = GameDataBuilder::new()
         .with_bundle(render_bundle.with_sprite_sheet_processor())?
         .with_bundle(input_bundle)?
-        .with(MouseSystem, "mouse-system", &[])
+        .with(MouseSystem::<StringBindings>::new(), "mouse-system", &[])
         .with(ClickSystem, "click-system", &[]);
 
     let mut game = Application::new(
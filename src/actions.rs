@@ -0,0 +1,257 @@
+use super::*;
+use super::replay::InputReplay;
+use std::{
+    borrow::Borrow,
+    collections::HashMap,
+    hash::Hash
+};
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ActionState {
+    pub pressed: bool,
+    pub just_pressed: bool,
+    pub just_released: bool,
+    // 押している間の経過秒数（離したフレームまでは残る）
+    pub held_duration: f32,
+}
+
+// bindings.ron の actions ごとの状態
+pub struct Actions<T: BindingTypes = StringBindings> {
+    states: HashMap<T::Action, ActionState>,
+}
+
+impl<T: BindingTypes> Default for Actions<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T: BindingTypes> Actions<T> {
+    pub fn new() -> Self {
+        Actions {
+            states: HashMap::new(),
+        }
+    }
+
+    pub fn state<A>(&self, action: &A) -> Option<&ActionState>
+    where
+        T::Action: Borrow<A>,
+        A: Hash + Eq + ?Sized,
+    {
+        self.states.get(action)
+    }
+
+    pub fn get<A>(&self, action: &A) -> bool
+    where
+        T::Action: Borrow<A>,
+        A: Hash + Eq + ?Sized,
+    {
+        self.state(action).map_or(false, |state| state.pressed)
+    }
+
+    pub fn get_down<A>(&self, action: &A) -> bool
+    where
+        T::Action: Borrow<A>,
+        A: Hash + Eq + ?Sized,
+    {
+        self.state(action).map_or(false, |state| state.just_pressed)
+    }
+
+    pub fn get_up<A>(&self, action: &A) -> bool
+    where
+        T::Action: Borrow<A>,
+        A: Hash + Eq + ?Sized,
+    {
+        self.state(action).map_or(false, |state| state.just_released)
+    }
+
+    pub fn held_duration<A>(&self, action: &A) -> f32
+    where
+        T::Action: Borrow<A>,
+        A: Hash + Eq + ?Sized,
+    {
+        self.state(action).map_or(0.0, |state| state.held_duration)
+    }
+
+    pub fn actions(&self) -> impl Iterator<Item = (&T::Action, &ActionState)> {
+        self.states.iter()
+    }
+
+    fn update(&mut self, input: &InputHandler<T>, delta: f32) {
+        // bindings から消えたアクションは捨てる
+        let bindings = &input.bindings;
+        self.states.retain(|action, _| bindings.actions().any(|a| a == action));
+        for action in input.bindings.actions() {
+            let down = input.action_is_down(action).unwrap_or(false);
+            let state = self.states.entry(action.clone()).or_default();
+            state.just_pressed = down && !state.pressed;
+            state.just_released = !down && state.pressed;
+            if state.just_pressed {
+                state.held_duration = 0.0;
+            } else if down {
+                state.held_duration += delta;
+            } else if !state.just_released {
+                state.held_duration = 0.0;
+            }
+            state.pressed = down;
+        }
+    }
+}
+
+pub struct ActionsSystem<T: BindingTypes = StringBindings> {
+    _marker: PhantomData<T>,
+}
+
+impl<T: BindingTypes> ActionsSystem<T> {
+    pub fn new() -> Self {
+        ActionsSystem {
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<T: BindingTypes> Default for ActionsSystem<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<'s, T: BindingTypes> System<'s> for ActionsSystem<T> {
+    type SystemData = (
+        Write<'s, Actions<T>>,
+        Read<'s, InputHandler<T>>,
+        Read<'s, Time>,
+        Read<'s, InputReplay>,
+    );
+
+    fn run(&mut self, (mut actions, input, time, replay): Self::SystemData) {
+        actions.update(&input, replay.delta_seconds(&time));
+    }
+}
+
+pub struct ActionsBundle<'a, T: BindingTypes = StringBindings> {
+    dep: &'a [&'a str],
+    _marker: PhantomData<T>,
+}
+
+impl<'a, T: BindingTypes> Default for ActionsBundle<'a, T> {
+    fn default() -> Self {
+        ActionsBundle {
+            dep: &[],
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<'a, T: BindingTypes> ActionsBundle<'a, T> {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    pub fn with_dep(mut self, dep: &'a [&'a str]) -> Self {
+        self.dep = dep;
+        self
+    }
+}
+
+impl<'a, 'b, 'c, T: BindingTypes> SystemBundle<'a, 'b> for ActionsBundle<'c, T> {
+    fn build(self, builder: &mut DispatcherBuilder<'a, 'b>) -> Result<(), Error> {
+        let dep = {
+            let mut vec = self.dep.to_vec();
+            vec.push("input_system");
+            vec
+        };
+        builder.add(
+            ActionsSystem::<T>::new(),
+            "actions_system",
+            &dep,
+        );
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use amethyst::{
+        input::Button,
+        winit::{
+            DeviceId, KeyboardInput, ModifiersState, WindowId
+        },
+    };
+
+    fn send(input: &mut InputHandler<StringBindings>, key: VirtualKeyCode, state: ElementState) {
+        let event = Event::WindowEvent {
+            window_id: unsafe { WindowId::dummy() },
+            event: WindowEvent::KeyboardInput {
+                device_id: unsafe { DeviceId::dummy() },
+                input: KeyboardInput {
+                    scancode: 0,
+                    state,
+                    virtual_keycode: Some(key),
+                    modifiers: ModifiersState::default(),
+                },
+            },
+        };
+        input.send_event(&event, &mut EventChannel::new(), 1.0);
+    }
+
+    #[test]
+    fn actions_report_edges_and_hold_time() {
+        let mut input = InputHandler::<StringBindings>::new();
+        input
+            .bindings
+            .insert_action_binding("jump".to_string(), vec![Button::Key(VirtualKeyCode::Space)])
+            .unwrap();
+        let mut actions = Actions::new();
+        actions.update(&input, 0.1);
+        assert!(!actions.get("jump"));
+        assert_eq!(actions.actions().count(), 1);
+
+        send(&mut input, VirtualKeyCode::Space, ElementState::Pressed);
+        actions.update(&input, 0.1);
+        assert!(actions.get("jump") && actions.get_down("jump"));
+        assert_eq!(actions.held_duration("jump"), 0.0);
+
+        // 押した瞬間は 1 フレームだけ
+        actions.update(&input, 0.1);
+        assert!(actions.get("jump") && !actions.get_down("jump"));
+        actions.update(&input, 0.25);
+        assert!((actions.held_duration("jump") - 0.35).abs() < 1e-6);
+
+        // 離したフレームでは押していた時間が残る
+        send(&mut input, VirtualKeyCode::Space, ElementState::Released);
+        actions.update(&input, 0.1);
+        assert!(!actions.get("jump") && actions.get_up("jump"));
+        assert!((actions.held_duration("jump") - 0.35).abs() < 1e-6);
+
+        actions.update(&input, 0.1);
+        assert!(!actions.get_up("jump"));
+        assert_eq!(actions.held_duration("jump"), 0.0);
+        assert_eq!(actions.state("jump"), Some(&ActionState::default()));
+    }
+
+    #[test]
+    fn actions_removed_from_bindings_are_pruned() {
+        let mut input = InputHandler::<StringBindings>::new();
+        input
+            .bindings
+            .insert_action_binding("jump".to_string(), vec![Button::Key(VirtualKeyCode::Space)])
+            .unwrap();
+        input
+            .bindings
+            .insert_action_binding("fire".to_string(), vec![Button::Key(VirtualKeyCode::F)])
+            .unwrap();
+        let mut actions = Actions::new();
+        send(&mut input, VirtualKeyCode::Space, ElementState::Pressed);
+        actions.update(&input, 0.1);
+        assert!(actions.get("jump"));
+
+        input.bindings.remove_action_binding("jump", &[Button::Key(VirtualKeyCode::Space)]).unwrap();
+        actions.update(&input, 0.1);
+        assert!(actions.state("jump").is_none());
+        assert!(!actions.get("jump"));
+        let names: Vec<&String> = actions.actions().map(|(action, _)| action).collect();
+        assert_eq!(names, vec!["fire"]);
+    }
+}
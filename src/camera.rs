@@ -0,0 +1,1171 @@
+use super::*;
+use super::mouse::Mouse;
+use amethyst::{
+    core::{
+        math::UnitQuaternion,
+        Parent
+    },
+    ecs::prelude::{
+        Component, DenseVecStorage, Entities, WriteStorage
+    },
+    ui::{
+        Anchor, UiImage, UiTransform
+    },
+};
+use std::f32::consts::FRAC_PI_3;
+
+// ワールド座標の原点 (0, 0) を画面のどこに置くか
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CameraOrigin {
+    // 画面の中心
+    Center,
+    // 画面の左下（カメラはサイズの半分の位置に置かれる）
+    BottomLeft,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CameraBuilder {
+    width: f32,
+    height: f32,
+    origin: CameraOrigin,
+    near: f32,
+    far: f32,
+    z: f32,
+    zoom: f32,
+    // 垂直方向の視野角（ラジアン）。None なら正射影
+    fov: Option<f32>,
+    active: bool,
+    scale_policy: Option<ScalePolicy>,
+}
+
+impl CameraBuilder {
+    // width, height はズーム 1.0 のときに映るワールドの大きさ
+    pub fn new(width: f32, height: f32) -> Self {
+        CameraBuilder {
+            width,
+            height,
+            origin: CameraOrigin::BottomLeft,
+            near: 0.1,
+            far: 2000.0,
+            z: 10.0,
+            zoom: 1.0,
+            fov: None,
+            active: true,
+            scale_policy: None,
+        }
+    }
+
+    pub fn with_origin(mut self, origin: CameraOrigin) -> Self {
+        self.origin = origin;
+        self
+    }
+
+    pub fn with_clip(mut self, near: f32, far: f32) -> Self {
+        self.near = near;
+        self.far = far;
+        self
+    }
+
+    // 正射影でのカメラの z。これより手前（大きい z）のスプライトは映らない
+    pub fn with_z(mut self, z: f32) -> Self {
+        self.z = z;
+        self
+    }
+
+    // 2.0 で 2 倍に拡大
+    pub fn with_zoom(mut self, zoom: f32) -> Self {
+        self.zoom = zoom;
+        self
+    }
+
+    // z = 0 の平面で width, height が映る距離にカメラを置く
+    pub fn with_perspective(mut self, fov: f32) -> Self {
+        self.fov = Some(fov);
+        self
+    }
+
+    pub fn with_standard_perspective(self) -> Self {
+        self.with_perspective(FRAC_PI_3)
+    }
+
+    // false なら ActiveCamera を書き換えない
+    pub fn with_active(mut self, active: bool) -> Self {
+        self.active = active;
+        self
+    }
+
+    // width, height を仮想解像度として VirtualResolution を付ける
+    pub fn with_scale_policy(mut self, policy: ScalePolicy) -> Self {
+        self.scale_policy = Some(policy);
+        self
+    }
+
+    pub fn projection(&self) -> Projection {
+        match self.fov {
+            Some(fov) => Projection::perspective(self.width / self.height, fov, self.near, self.far),
+            None => {
+                let (half_w, half_h) = (self.width * 0.5 / self.zoom, self.height * 0.5 / self.zoom);
+                Projection::orthographic(-half_w, half_w, -half_h, half_h, self.near, self.far)
+            }
+        }
+    }
+
+    pub fn transform(&self) -> Transform {
+        let (x, y) = match self.origin {
+            CameraOrigin::Center => (0.0, 0.0),
+            CameraOrigin::BottomLeft => (self.width * 0.5, self.height * 0.5),
+        };
+        let z = match self.fov {
+            // 透視投影ではズームをカメラの距離で表す
+            Some(fov) => self.height * 0.5 / (fov * 0.5).tan() / self.zoom,
+            None => self.z,
+        };
+        Transform::from_xyz(x, y, z)
+    }
+
+    pub fn build(self, world: &mut World) -> Entity {
+        if self.scale_policy.is_some() {
+            world.register::<VirtualResolution>();
+        }
+        let mut builder = world
+            .create_entity()
+            .with(Camera::from(self.projection()))
+            .with(self.transform());
+        if let Some(policy) = self.scale_policy {
+            builder = builder.with(
+                VirtualResolution::new(self.width, self.height, policy).with_zoom(self.zoom)
+            );
+        }
+        let entity = builder.build();
+        if self.active {
+            world.add_resource(ActiveCamera { entity: Some(entity) });
+        }
+        entity
+    }
+}
+
+// カメラのエンティティに付けて target を追いかける
+pub struct CameraFollow {
+    pub target: Entity,
+    // 1 秒あたりの追従の速さ（指数的に近づく）。0.0 ならすぐに追いつく
+    pub smoothing: f32,
+    // この幅と高さの範囲内で target が動いてもカメラは動かない
+    pub dead_zone: (f32, f32),
+    // target の速度の何秒分先を見るか
+    pub look_ahead: f32,
+    // 映す範囲をワールド座標の左下と右上の内側に収める
+    pub bounds: Option<((f32, f32), (f32, f32))>,
+    pub offset: (f32, f32),
+
+    position: Option<(f32, f32)>,
+    goal: Option<(f32, f32)>,
+    last_target: Option<(f32, f32)>,
+    velocity: (f32, f32),
+}
+
+impl Component for CameraFollow {
+    type Storage = DenseVecStorage<Self>;
+}
+
+impl CameraFollow {
+    pub fn new(target: Entity) -> Self {
+        CameraFollow {
+            target,
+            smoothing: 0.0,
+            dead_zone: (0.0, 0.0),
+            look_ahead: 0.0,
+            bounds: None,
+            offset: (0.0, 0.0),
+            position: None,
+            goal: None,
+            last_target: None,
+            velocity: (0.0, 0.0),
+        }
+    }
+
+    pub fn with_smoothing(mut self, smoothing: f32) -> Self {
+        self.smoothing = smoothing;
+        self
+    }
+
+    pub fn with_dead_zone(mut self, w: f32, h: f32) -> Self {
+        self.dead_zone = (w, h);
+        self
+    }
+
+    pub fn with_look_ahead(mut self, seconds: f32) -> Self {
+        self.look_ahead = seconds;
+        self
+    }
+
+    pub fn with_bounds(mut self, min: (f32, f32), max: (f32, f32)) -> Self {
+        self.bounds = Some((min, max));
+        self
+    }
+
+    pub fn with_offset(mut self, x: f32, y: f32) -> Self {
+        self.offset = (x, y);
+        self
+    }
+
+    // 次のフレームで target の位置にすぐ移動する（シーンの切り替えなど）
+    pub fn snap(&mut self) {
+        self.position = None;
+        self.goal = None;
+        self.last_target = None;
+        self.velocity = (0.0, 0.0);
+    }
+
+    // 追従の結果のカメラ位置（揺れなどのオフセットを含まない）
+    pub fn position(&self) -> Option<(f32, f32)> {
+        self.position
+    }
+
+    fn update(
+        &mut self,
+        target: (f32, f32),
+        half_extents: Option<(f32, f32)>,
+        delta: f32,
+    ) {
+        if delta > 0.0 {
+            if let Some(last) = self.last_target {
+                let velocity = ((target.0 - last.0) / delta, (target.1 - last.1) / delta);
+                // 速度も少しならしておかないと先読みがガタつく
+                let t = blend(self.smoothing, delta);
+                self.velocity.0 += (velocity.0 - self.velocity.0) * t;
+                self.velocity.1 += (velocity.1 - self.velocity.1) * t;
+            }
+        }
+        self.last_target = Some(target);
+
+        let look = (
+            target.0 + self.velocity.0 * self.look_ahead,
+            target.1 + self.velocity.1 * self.look_ahead,
+        );
+        let goal = self.goal.get_or_insert(look);
+        goal.0 = push_out(goal.0, look.0, self.dead_zone.0 * 0.5);
+        goal.1 = push_out(goal.1, look.1, self.dead_zone.1 * 0.5);
+        let goal = (goal.0 + self.offset.0, goal.1 + self.offset.1);
+
+        let position = match self.position {
+            Some((x, y)) => {
+                let t = blend(self.smoothing, delta);
+                (x + (goal.0 - x) * t, y + (goal.1 - y) * t)
+            }
+            None => goal,
+        };
+        let position = match (self.bounds, half_extents) {
+            (Some((min, max)), Some((half_w, half_h))) => (
+                clamp_view(position.0, min.0, max.0, half_w),
+                clamp_view(position.1, min.1, max.1, half_h),
+            ),
+            (Some((min, max)), None) => (
+                clamp_view(position.0, min.0, max.0, 0.0),
+                clamp_view(position.1, min.1, max.1, 0.0),
+            ),
+            _ => position,
+        };
+        self.position = Some(position);
+    }
+}
+
+// 指数平滑の係数。フレームレートによらず同じ速さで近づく
+fn blend(smoothing: f32, delta: f32) -> f32 {
+    if smoothing <= 0.0 {
+        1.0
+    } else {
+        1.0 - (-smoothing * delta).exp()
+    }
+}
+
+// value が center ± half の外に出たら、value が端に来るように center をずらす
+fn push_out(center: f32, value: f32, half: f32) -> f32 {
+    if value > center + half {
+        value - half
+    } else if value < center - half {
+        value + half
+    } else {
+        center
+    }
+}
+
+// 映す範囲が min ~ max に収まるようにする。範囲より画面の方が大きければ中央に置く
+fn clamp_view(center: f32, min: f32, max: f32, half: f32) -> f32 {
+    if max - min <= half * 2.0 {
+        (min + max) * 0.5
+    } else {
+        center.clamp(min + half, max - half)
+    }
+}
+
+pub struct CameraFollowSystem;
+
+impl<'s> System<'s> for CameraFollowSystem {
+    type SystemData = (
+        WriteStorage<'s, CameraFollow>,
+        ReadStorage<'s, Camera>,
+        ReadStorage<'s, Parent>,
+        WriteStorage<'s, Transform>,
+        Read<'s, Time>,
+    );
+
+    fn run(&mut self, (mut follows, cameras, parents, mut transforms, time): Self::SystemData) {
+        let delta = time.delta_seconds();
+        for (follow, camera) in (&mut follows, &cameras).join() {
+            let target = match transforms.get(follow.target) {
+                // TransformSystem より前に動くので、親がなければこのフレームで動かした位置を使う
+                Some(transform) if !parents.contains(follow.target) => {
+                    let translation = transform.translation();
+                    (translation.x, translation.y)
+                }
+                // 親があると前のフレームの GlobalTransform になる（1 フレーム遅れる）
+                Some(transform) => {
+                    let matrix = transform.global_matrix();
+                    (matrix[(0, 3)], matrix[(1, 3)])
+                }
+                None => continue,
+            };
+            let half_extents = camera.projection().as_orthographic().map(|ortho| {
+                ((ortho.right() - ortho.left()) * 0.5, (ortho.top() - ortho.bottom()) * 0.5)
+            });
+            follow.update(target, half_extents, delta);
+        }
+        // target の Transform を読み終えてからカメラに書き込む
+        for (follow, transform) in (&follows, &mut transforms).join() {
+            if let Some((x, y)) = follow.position {
+                transform.set_translation_x(x);
+                transform.set_translation_y(y);
+            }
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct CameraFollowBundle<'a> {
+    dep: &'a [&'a str]
+}
+
+impl<'a> CameraFollowBundle<'a> {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    pub fn with_dep(mut self, dep: &'a [&'a str]) -> Self {
+        self.dep = dep;
+        self
+    }
+}
+
+// target を動かすシステムを dep に入れ、TransformBundle より前に追加すること。
+// 親のある target は前のフレームの位置を追うので、遅れが気になるなら親のない target にする
+impl<'a, 'b, 'c> SystemBundle<'a, 'b> for CameraFollowBundle<'c> {
+    fn build(self, builder: &mut DispatcherBuilder<'a, 'b>) -> Result<(), Error> {
+        builder.add(
+            CameraFollowSystem,
+            "camera_follow_system",
+            self.dep,
+        );
+        Ok(())
+    }
+}
+
+// カメラの揺れ。trauma の 2 乗に比例して揺れ、時間とともに減っていく。
+// カメラの Transform は書き換えず、揺れの分だけずらした子のカメラ（ShakeView）で描画する
+pub struct CameraShake {
+    pub trauma: f32,
+    // 1 秒あたりに減る trauma
+    pub decay: f32,
+    // trauma が 1.0 のときの最大のずれ
+    pub max_offset: (f32, f32),
+    pub max_roll: f32,
+    // ノイズの速さ（1 秒あたりの山の数）
+    pub frequency: f32,
+    // punch / kick が戻る速さ（1 秒あたり、指数的）
+    pub punch_decay: f32,
+
+    seed: u32,
+    time: f32,
+    punch: (f32, f32),
+    kick: f32,
+    view: Option<Entity>,
+}
+
+impl Component for CameraShake {
+    type Storage = DenseVecStorage<Self>;
+}
+
+impl CameraShake {
+    // 同じ seed なら同じ揺れ方になる
+    pub fn new(seed: u32) -> Self {
+        CameraShake {
+            trauma: 0.0,
+            decay: 1.0,
+            max_offset: (16.0, 16.0),
+            max_roll: 0.1,
+            frequency: 15.0,
+            punch_decay: 10.0,
+            seed,
+            time: 0.0,
+            punch: (0.0, 0.0),
+            kick: 0.0,
+            view: None,
+        }
+    }
+
+    pub fn with_decay(mut self, decay: f32) -> Self {
+        self.decay = decay;
+        self
+    }
+
+    pub fn with_max_offset(mut self, x: f32, y: f32) -> Self {
+        self.max_offset = (x, y);
+        self
+    }
+
+    pub fn with_max_roll(mut self, roll: f32) -> Self {
+        self.max_roll = roll;
+        self
+    }
+
+    pub fn with_frequency(mut self, frequency: f32) -> Self {
+        self.frequency = frequency;
+        self
+    }
+
+    pub fn with_punch_decay(mut self, punch_decay: f32) -> Self {
+        self.punch_decay = punch_decay;
+        self
+    }
+
+    // 0.0 ~ 1.0 に収める
+    pub fn add_trauma(&mut self, amount: f32) {
+        self.trauma = (self.trauma + amount).clamp(0.0, 1.0);
+    }
+
+    // 指定した方向にずらして、そこから戻す
+    pub fn punch(&mut self, x: f32, y: f32) {
+        self.punch.0 += x;
+        self.punch.1 += y;
+    }
+
+    // 回転方向の punch（ラジアン）
+    pub fn kick(&mut self, roll: f32) {
+        self.kick += roll;
+    }
+
+    // 今のずれ（x, y, 回転）
+    pub fn offset(&self) -> (f32, f32, f32) {
+        let shake = self.trauma * self.trauma;
+        let t = self.time * self.frequency;
+        (
+            self.max_offset.0 * shake * noise(self.seed, 0, t) + self.punch.0,
+            self.max_offset.1 * shake * noise(self.seed, 1, t) + self.punch.1,
+            self.max_roll * shake * noise(self.seed, 2, t) + self.kick,
+        )
+    }
+
+    // 描画に使われている子のカメラ
+    pub fn view(&self) -> Option<Entity> {
+        self.view
+    }
+
+    fn update(&mut self, delta: f32) {
+        self.time += delta;
+        self.trauma = (self.trauma - self.decay * delta).max(0.0);
+        let t = (-self.punch_decay * delta).exp();
+        self.punch.0 *= t;
+        self.punch.1 *= t;
+        self.kick *= t;
+    }
+
+    // 親のカメラから見たずれ
+    fn apply(&self, transform: &mut Transform) {
+        let (x, y, roll) = self.offset();
+        *transform.translation_mut() = Vector3::new(x, y, 0.0);
+        *transform.rotation_mut() = UnitQuaternion::from_axis_angle(&Vector3::z_axis(), roll);
+    }
+}
+
+// CameraShake を付けたカメラの代わりに描画する子のカメラ。
+// ActiveCamera はこちらを指すが、マウスの座標などは揺れていない camera で計算する
+pub struct ShakeView {
+    pub camera: Entity,
+}
+
+impl Component for ShakeView {
+    type Storage = DenseVecStorage<Self>;
+}
+
+// seed と channel ごとに決まる -1.0 ~ 1.0 の滑らかなノイズ
+fn noise(seed: u32, channel: u32, t: f32) -> f32 {
+    let i = t.floor();
+    let f = t - i;
+    let a = lattice(seed, channel, i as i32);
+    let b = lattice(seed, channel, i as i32 + 1);
+    let s = f * f * (3.0 - 2.0 * f);
+    a + (b - a) * s
+}
+
+fn lattice(seed: u32, channel: u32, i: i32) -> f32 {
+    let mut h = seed
+        ^ channel.wrapping_mul(0x9e37_79b9)
+        ^ (i as u32).wrapping_mul(0x85eb_ca6b);
+    h ^= h >> 16;
+    h = h.wrapping_mul(0x7feb_352d);
+    h ^= h >> 15;
+    h = h.wrapping_mul(0x846c_a68b);
+    h ^= h >> 16;
+    h as f32 / u32::MAX as f32 * 2.0 - 1.0
+}
+
+pub struct CameraShakeSystem;
+
+impl<'s> System<'s> for CameraShakeSystem {
+    type SystemData = (
+        Entities<'s>,
+        WriteStorage<'s, CameraShake>,
+        WriteStorage<'s, ShakeView>,
+        WriteStorage<'s, Camera>,
+        WriteStorage<'s, Transform>,
+        WriteStorage<'s, Parent>,
+        Write<'s, ActiveCamera>,
+        Read<'s, Time>,
+    );
+
+    fn run(
+        &mut self,
+        (
+            entities, mut shakes, mut views, mut cameras, mut transforms, mut parents,
+            mut active_camera, time
+        ): Self::SystemData
+    ) {
+        // CameraShake が外されたカメラの子は消して、ActiveCamera を元に戻す
+        for (entity, view) in (&entities, &views).join() {
+            if shakes.get(view.camera).map_or(false, |shake| shake.view == Some(entity)) {
+                continue;
+            }
+            if active_camera.entity == Some(entity) {
+                active_camera.entity = Some(view.camera).filter(|e| entities.is_alive(*e));
+            }
+            let _ = entities.delete(entity);
+        }
+
+        let delta = time.delta_seconds();
+        for (entity, shake) in (&entities, &mut shakes).join() {
+            shake.update(delta);
+            let camera = match cameras.get(entity) {
+                Some(camera) => camera.clone(),
+                None => continue,
+            };
+            let view = match shake.view.filter(|view| entities.is_alive(*view)) {
+                Some(view) => view,
+                None => {
+                    let view = entities.create();
+                    let _ = parents.insert(view, Parent { entity });
+                    let _ = views.insert(view, ShakeView { camera: entity });
+                    let _ = transforms.insert(view, Transform::default());
+                    shake.view = Some(view);
+                    view
+                }
+            };
+            // 投影は毎フレーム親に合わせる（ズームや VirtualResolution の変更を反映する）
+            let _ = cameras.insert(view, camera);
+            if let Some(transform) = transforms.get_mut(view) {
+                shake.apply(transform);
+            }
+            if active_camera.entity.map_or(true, |active| active == entity) {
+                active_camera.entity = Some(view);
+            }
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct CameraShakeBundle<'a> {
+    dep: &'a [&'a str]
+}
+
+impl<'a> CameraShakeBundle<'a> {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    pub fn with_dep(mut self, dep: &'a [&'a str]) -> Self {
+        self.dep = dep;
+        self
+    }
+}
+
+// カメラを動かすシステム（CameraFollowBundle や EditorCameraBundle、VirtualResolutionBundle）の後、
+// TransformBundle より前に追加すること
+impl<'a, 'b, 'c> SystemBundle<'a, 'b> for CameraShakeBundle<'c> {
+    fn build(self, builder: &mut DispatcherBuilder<'a, 'b>) -> Result<(), Error> {
+        builder.add(
+            CameraShakeSystem,
+            "camera_shake_system",
+            self.dep,
+        );
+        Ok(())
+    }
+}
+
+// エディタ風のカメラ操作。ホイールでカーソル位置に向かってズームし、中ボタンのドラッグで動かす。
+// 正射影の範囲を書き換えるので、スプライトの大きさは変えない
+pub struct EditorCamera {
+    pub zoom: f32,
+    pub min_zoom: f32,
+    pub max_zoom: f32,
+    // ホイール 1 行あたりの倍率
+    pub zoom_step: f32,
+    pub pan_button: Option<MouseButton>,
+
+    last_screen: Option<(f32, f32)>,
+}
+
+impl Component for EditorCamera {
+    type Storage = DenseVecStorage<Self>;
+}
+
+impl Default for EditorCamera {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl EditorCamera {
+    pub fn new() -> Self {
+        EditorCamera {
+            zoom: 1.0,
+            min_zoom: 0.25,
+            max_zoom: 8.0,
+            zoom_step: 1.1,
+            pan_button: Some(MouseButton::Middle),
+            last_screen: None,
+        }
+    }
+
+    // CameraBuilder::with_zoom を使ったときは同じ値を渡す
+    pub fn with_zoom(mut self, zoom: f32) -> Self {
+        self.zoom = zoom;
+        self
+    }
+
+    pub fn with_zoom_limits(mut self, min: f32, max: f32) -> Self {
+        self.min_zoom = min;
+        self.max_zoom = max;
+        self
+    }
+
+    pub fn with_zoom_step(mut self, step: f32) -> Self {
+        self.zoom_step = step;
+        self
+    }
+
+    pub fn with_pan_button(mut self, button: Option<MouseButton>) -> Self {
+        self.pan_button = button;
+        self
+    }
+}
+
+pub struct EditorCameraSystem<T: BindingTypes = StringBindings> {
+    _marker: PhantomData<T>,
+}
+
+impl<T: BindingTypes> EditorCameraSystem<T> {
+    pub fn new() -> Self {
+        EditorCameraSystem {
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<T: BindingTypes> Default for EditorCameraSystem<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<'s, T: BindingTypes> System<'s> for EditorCameraSystem<T> {
+    type SystemData = (
+        Read<'s, Mouse<T>>,
+        ReadExpect<'s, ScreenDimensions>,
+        WriteStorage<'s, EditorCamera>,
+        WriteStorage<'s, Camera>,
+        WriteStorage<'s, Transform>,
+    );
+
+    fn run(
+        &mut self,
+        (mouse, screen, mut editors, mut cameras, mut transforms): Self::SystemData
+    ) {
+        let (w, h) = (screen.width(), screen.height());
+        if w <= 0.0 || h <= 0.0 {
+            return;
+        }
+        let (sx, sy) = mouse.screen_position();
+        for (editor, camera, transform) in (&mut editors, &mut cameras, &mut transforms).join() {
+            let mut projection = camera.projection().clone();
+            let ortho = match projection.as_orthographic_mut() {
+                Some(ortho) => ortho,
+                None => continue,
+            };
+
+            // 中ボタンのドラッグ。カーソルの下のワールド座標が動かないようにカメラを動かす
+            let panning = editor.pan_button.map_or(false, |button| mouse.get(button));
+            if panning {
+                if let Some((lx, ly)) = editor.last_screen {
+                    let per_pixel_x = (ortho.right() - ortho.left()) / w;
+                    let per_pixel_y = (ortho.top() - ortho.bottom()) / h;
+                    transform.prepend_translation_x(-(sx - lx) * per_pixel_x);
+                    transform.prepend_translation_y((sy - ly) * per_pixel_y);
+                }
+                editor.last_screen = Some((sx, sy));
+            } else {
+                editor.last_screen = None;
+            }
+
+            if mouse.scroll_y == 0.0 {
+                continue;
+            }
+            let zoom = (editor.zoom * editor.zoom_step.powf(mouse.scroll_y))
+                .clamp(editor.min_zoom, editor.max_zoom);
+            if zoom == editor.zoom {
+                continue;
+            }
+            // カメラから見たカーソルの位置。拡大の前後でここが同じワールド座標を指すようにする
+            let view_x = ortho.left() + sx / w * (ortho.right() - ortho.left());
+            let view_y = ortho.top() - sy / h * (ortho.top() - ortho.bottom());
+            let scale = editor.zoom / zoom;
+            ortho.set_left_and_right(ortho.left() * scale, ortho.right() * scale);
+            ortho.set_bottom_and_top(ortho.bottom() * scale, ortho.top() * scale);
+            transform.prepend_translation_x(view_x * (1.0 - scale));
+            transform.prepend_translation_y(view_y * (1.0 - scale));
+            editor.zoom = zoom;
+            camera.set_projection(projection);
+        }
+    }
+}
+
+pub struct EditorCameraBundle<'a, T: BindingTypes = StringBindings> {
+    dep: &'a [&'a str],
+    _marker: PhantomData<T>,
+}
+
+impl<'a, T: BindingTypes> Default for EditorCameraBundle<'a, T> {
+    fn default() -> Self {
+        EditorCameraBundle {
+            dep: &[],
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<'a, T: BindingTypes> EditorCameraBundle<'a, T> {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    pub fn with_dep(mut self, dep: &'a [&'a str]) -> Self {
+        self.dep = dep;
+        self
+    }
+}
+
+// MouseBundle の後、TransformBundle より前に追加すること
+impl<'a, 'b, 'c, T: BindingTypes> SystemBundle<'a, 'b> for EditorCameraBundle<'c, T> {
+    fn build(self, builder: &mut DispatcherBuilder<'a, 'b>) -> Result<(), Error> {
+        let dep = {
+            let mut vec = self.dep.to_vec();
+            vec.push("mouse_system");
+            vec
+        };
+        builder.add(
+            EditorCameraSystem::<T>::new(),
+            "editor_camera_system",
+            &dep,
+        );
+        Ok(())
+    }
+}
+
+// ウィンドウの大きさが変わったときに仮想解像度をどう画面に合わせるか
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ScalePolicy {
+    // 縦横比を保って全体が入るように拡大し、余った部分は帯になる
+    Letterbox,
+    // 幅を合わせる（高さははみ出すか余る）
+    FitWidth,
+    // 高さを合わせる（幅ははみ出すか余る）
+    FitHeight,
+    // Letterbox と同じ倍率で、余った部分にもワールドを映す
+    Expand,
+    // 整数倍だけで拡大する。余った部分は帯になる
+    PixelPerfect,
+}
+
+// カメラのエンティティに付ける。正射影の範囲はウィンドウの大きさに合わせて作り直される
+#[derive(Debug, Clone, PartialEq)]
+pub struct VirtualResolution {
+    pub width: f32,
+    pub height: f32,
+    pub policy: ScalePolicy,
+    // EditorCamera がないときのズーム
+    pub zoom: f32,
+
+    bar_color: [f32; 4],
+    bars: Vec<Entity>,
+    // 最後に反映したときのウィンドウの大きさと倍率
+    applied: Option<(f32, f32, ScalePolicy, f32)>,
+    scale: f32,
+    viewport: (f32, f32, f32, f32),
+}
+
+impl Component for VirtualResolution {
+    type Storage = DenseVecStorage<Self>;
+}
+
+impl VirtualResolution {
+    pub fn new(width: f32, height: f32, policy: ScalePolicy) -> Self {
+        VirtualResolution {
+            width,
+            height,
+            policy,
+            zoom: 1.0,
+            bar_color: [0.0, 0.0, 0.0, 1.0],
+            bars: Vec::new(),
+            applied: None,
+            scale: 1.0,
+            viewport: (0.0, 0.0, width, height),
+        }
+    }
+
+    pub fn with_zoom(mut self, zoom: f32) -> Self {
+        self.zoom = zoom;
+        self
+    }
+
+    // Letterbox と PixelPerfect の帯の色
+    pub fn with_bar_color(mut self, color: [f32; 4]) -> Self {
+        self.bar_color = color;
+        self
+    }
+
+    // 仮想解像度 1 ピクセルあたりの物理ピクセル数
+    pub fn scale(&self) -> f32 {
+        self.scale
+    }
+
+    // 仮想解像度の範囲が映っている画面上の矩形（左上原点の物理ピクセルで x, y, 幅, 高さ）
+    pub fn viewport(&self) -> (f32, f32, f32, f32) {
+        self.viewport
+    }
+
+    pub fn contains_screen(&self, (x, y): (f32, f32)) -> bool {
+        let (vx, vy, vw, vh) = self.viewport;
+        x >= vx && x <= vx + vw && y >= vy && y <= vy + vh
+    }
+
+    fn has_bars(&self) -> bool {
+        matches!(self.policy, ScalePolicy::Letterbox | ScalePolicy::PixelPerfect)
+    }
+
+    // 画面全体に映すワールドの幅と高さ（ズーム 1.0）
+    fn fit(&mut self, w: f32, h: f32) -> (f32, f32) {
+        let (sx, sy) = (w / self.width, h / self.height);
+        let scale = match self.policy {
+            ScalePolicy::Letterbox | ScalePolicy::Expand => sx.min(sy),
+            ScalePolicy::FitWidth => sx,
+            ScalePolicy::FitHeight => sy,
+            // ウィンドウが仮想解像度より小さいときは整数倍にできないので縮小する
+            ScalePolicy::PixelPerfect => {
+                let scale = sx.min(sy);
+                if scale >= 1.0 { scale.floor() } else { scale }
+            }
+        };
+        self.scale = scale;
+        let (vw, vh) = (self.width * scale, self.height * scale);
+        self.viewport = match self.policy {
+            ScalePolicy::Expand => (0.0, 0.0, w, h),
+            _ => ((w - vw) * 0.5, (h - vh) * 0.5, vw, vh),
+        };
+        (w / scale, h / scale)
+    }
+}
+
+// Letterbox と PixelPerfect で仮想解像度の外側を隠す帯。VirtualResolutionSystem が作る
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LetterboxBar {
+    pub camera: Entity,
+}
+
+impl Component for LetterboxBar {
+    type Storage = DenseVecStorage<Self>;
+}
+
+pub struct VirtualResolutionSystem;
+
+impl<'s> System<'s> for VirtualResolutionSystem {
+    type SystemData = (
+        Entities<'s>,
+        ReadExpect<'s, ScreenDimensions>,
+        WriteStorage<'s, VirtualResolution>,
+        ReadStorage<'s, EditorCamera>,
+        WriteStorage<'s, Camera>,
+        WriteStorage<'s, LetterboxBar>,
+        WriteStorage<'s, UiTransform>,
+        WriteStorage<'s, UiImage>,
+    );
+
+    fn run(
+        &mut self,
+        (
+            entities, screen, mut resolutions, editors, mut cameras, mut bars, mut ui_transforms,
+            mut images
+        ): Self::SystemData
+    ) {
+        // VirtualResolution が外されたカメラの帯は消す
+        for (entity, bar) in (&entities, &bars).join() {
+            if resolutions.get(bar.camera).map_or(false, |resolution| resolution.bars.contains(&entity)) {
+                continue;
+            }
+            let _ = entities.delete(entity);
+        }
+
+        let (w, h) = (screen.width(), screen.height());
+        if w <= 0.0 || h <= 0.0 {
+            return;
+        }
+        for (entity, resolution, editor, camera) in
+            (&entities, &mut resolutions, editors.maybe(), &mut cameras).join()
+        {
+            // EditorCamera のズームは残す
+            let zoom = editor.map_or(resolution.zoom, |editor| editor.zoom);
+            let key = (w, h, resolution.policy, zoom);
+            if resolution.applied == Some(key) {
+                continue;
+            }
+            let (view_w, view_h) = resolution.fit(w, h);
+            let (half_w, half_h) = (view_w * 0.5 / zoom, view_h * 0.5 / zoom);
+            let mut projection = camera.projection().clone();
+            match projection.as_orthographic_mut() {
+                Some(ortho) => {
+                    ortho.set_left_and_right(-half_w, half_w);
+                    ortho.set_bottom_and_top(-half_h, half_h);
+                }
+                None => {
+                    if let Some(perspective) = projection.as_perspective_mut() {
+                        perspective.set_aspect(w / h);
+                    }
+                }
+            }
+            camera.set_projection(projection);
+            resolution.applied = Some(key);
+
+            if !resolution.has_bars() {
+                for bar in resolution.bars.drain(..) {
+                    let _ = entities.delete(bar);
+                }
+                continue;
+            }
+            if resolution.bars.is_empty() || !resolution.bars.iter().all(|bar| entities.is_alive(*bar)) {
+                for bar in resolution.bars.drain(..) {
+                    let _ = entities.delete(bar);
+                }
+                for (id, anchor) in [
+                    ("letterbox_left", Anchor::MiddleLeft),
+                    ("letterbox_right", Anchor::MiddleRight),
+                    ("letterbox_top", Anchor::TopMiddle),
+                    ("letterbox_bottom", Anchor::BottomMiddle),
+                ].iter() {
+                    let bar = entities.create();
+                    let _ = bars.insert(bar, LetterboxBar { camera: entity });
+                    let _ = ui_transforms.insert(bar, UiTransform::new(
+                        id.to_string(), anchor.clone(), anchor.clone(), 0.0, 0.0, 0.0, 0.0, 0.0
+                    ));
+                    let _ = images.insert(bar, UiImage::SolidColor(resolution.bar_color));
+                    resolution.bars.push(bar);
+                }
+            }
+            // 左右の帯は画面の高さいっぱい、上下の帯は画面の幅いっぱいに伸ばす
+            let (vx, vy, _, _) = resolution.viewport;
+            let (bar_w, bar_h) = (vx.max(0.0), vy.max(0.0));
+            let sizes = [(bar_w, h), (bar_w, h), (w, bar_h), (w, bar_h)];
+            for (bar, &(width, height)) in resolution.bars.iter().zip(sizes.iter()) {
+                if let Some(transform) = ui_transforms.get_mut(*bar) {
+                    transform.width = width;
+                    transform.height = height;
+                }
+            }
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct VirtualResolutionBundle<'a> {
+    dep: &'a [&'a str]
+}
+
+impl<'a> VirtualResolutionBundle<'a> {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    pub fn with_dep(mut self, dep: &'a [&'a str]) -> Self {
+        self.dep = dep;
+        self
+    }
+}
+
+// マウスのワールド座標が同じフレームの範囲で計算されるように、MouseBundle より前に追加すること。
+// 帯は UI として描くので、UiBundle と UI の描画パスも使うこと
+impl<'a, 'b, 'c> SystemBundle<'a, 'b> for VirtualResolutionBundle<'c> {
+    fn build(self, builder: &mut DispatcherBuilder<'a, 'b>) -> Result<(), Error> {
+        builder.add(
+            VirtualResolutionSystem,
+            "virtual_resolution_system",
+            self.dep,
+        );
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use amethyst::core::TransformBundle;
+
+    #[test]
+    fn follow_tracks_unparented_targets_without_lag() {
+        let mut world = World::new();
+        let mut builder = DispatcherBuilder::new()
+            .with(CameraFollowSystem, "camera_follow_system", &[]);
+        TransformBundle::new()
+            .with_dep(&["camera_follow_system"])
+            .build(&mut builder)
+            .unwrap();
+        let mut dispatcher = builder.build();
+        dispatcher.setup(&mut world.res);
+        world.write_resource::<Time>().set_delta_seconds(0.1);
+
+        let target = world.create_entity().with(Transform::from_xyz(10.0, 20.0, 0.0)).build();
+        let pivot = world.create_entity().with(Transform::from_xyz(100.0, 0.0, 0.0)).build();
+        let child = world
+            .create_entity()
+            .with(Transform::from_xyz(5.0, 5.0, 0.0))
+            .with(Parent { entity: pivot })
+            .build();
+        let camera = CameraBuilder::new(100.0, 100.0).build(&mut world);
+        let other = CameraBuilder::new(100.0, 100.0).with_active(false).build(&mut world);
+        world.write_storage::<CameraFollow>().insert(camera, CameraFollow::new(target)).unwrap();
+        world.write_storage::<CameraFollow>().insert(other, CameraFollow::new(child)).unwrap();
+        let position = |world: &World, entity: Entity| {
+            let translation = *world.read_storage::<Transform>().get(entity).unwrap().translation();
+            (translation.x, translation.y)
+        };
+
+        dispatcher.dispatch(&world.res);
+        world.maintain();
+        assert_eq!(position(&world, camera), (10.0, 20.0));
+
+        // 同じフレームで動かした位置に追いつく
+        world.write_storage::<Transform>().get_mut(target).unwrap().set_translation_x(30.0);
+        dispatcher.dispatch(&world.res);
+        assert_eq!(position(&world, camera), (30.0, 20.0));
+
+        // 親のある target は前のフレームで計算した GlobalTransform を追う
+        assert_eq!(position(&world, other), (105.0, 5.0));
+    }
+
+    #[test]
+    fn noise_is_deterministic() {
+        for i in -3..3 {
+            // 格子点ではその点の値そのものになる
+            assert_eq!(noise(7, 0, i as f32), lattice(7, 0, i));
+            assert_eq!(lattice(7, 0, i), lattice(7, 0, i));
+        }
+        let samples: Vec<f32> = (0..100).map(|i| noise(7, 1, i as f32 * 0.13)).collect();
+        let again: Vec<f32> = (0..100).map(|i| noise(7, 1, i as f32 * 0.13)).collect();
+        assert_eq!(samples, again);
+        assert!(samples.iter().all(|v| *v >= -1.0 && *v <= 1.0));
+        // シードやチャンネルが違えば別の揺れになる
+        let other_seed: Vec<f32> = (0..100).map(|i| noise(8, 1, i as f32 * 0.13)).collect();
+        let other_channel: Vec<f32> = (0..100).map(|i| noise(7, 2, i as f32 * 0.13)).collect();
+        assert_ne!(samples, other_seed);
+        assert_ne!(samples, other_channel);
+        // 格子点の間はなめらかにつながる
+        assert!((noise(7, 1, 2.999) - noise(7, 1, 3.0)).abs() < 0.01);
+    }
+
+    #[test]
+    fn shake_leaves_camera_transform_alone() {
+        let mut world = World::new();
+        let mut dispatcher = DispatcherBuilder::new()
+            .with(CameraShakeSystem, "camera_shake_system", &[])
+            .build();
+        dispatcher.setup(&mut world.res);
+        world.register::<CameraShake>();
+        world.write_resource::<Time>().set_delta_seconds(0.1);
+
+        let camera = CameraBuilder::new(100.0, 100.0).build(&mut world);
+        let logical = world.read_storage::<Transform>().get(camera).unwrap().clone();
+        let mut shake = CameraShake::new(7).with_decay(0.0);
+        shake.add_trauma(1.0);
+        shake.punch(3.0, 0.0);
+        world.write_storage::<CameraShake>().insert(camera, shake).unwrap();
+
+        for _ in 0..3 {
+            dispatcher.dispatch(&world.res);
+            world.maintain();
+        }
+
+        let view = world.read_storage::<CameraShake>().get(camera).unwrap().view().unwrap();
+        let transforms = world.read_storage::<Transform>();
+        assert_eq!(transforms.get(camera).unwrap(), &logical);
+        assert_ne!(transforms.get(view).unwrap(), &Transform::default());
+        assert_eq!(world.read_storage::<Parent>().get(view).unwrap().entity, camera);
+        assert_eq!(world.read_resource::<ActiveCamera>().entity, Some(view));
+    }
+
+    #[test]
+    fn letterbox_draws_bars_and_keeps_builder_zoom() {
+        let mut world = World::new();
+        let mut dispatcher = DispatcherBuilder::new()
+            .with(VirtualResolutionSystem, "virtual_resolution_system", &[])
+            .build();
+        dispatcher.setup(&mut world.res);
+        world.register::<Transform>();
+        world.add_resource(ScreenDimensions::new(200, 100, 1.0));
+
+        let camera = CameraBuilder::new(100.0, 100.0)
+            .with_zoom(2.0)
+            .with_scale_policy(ScalePolicy::Letterbox)
+            .build(&mut world);
+        dispatcher.dispatch(&world.res);
+        world.maintain();
+
+        {
+            let cameras = world.read_storage::<Camera>();
+            let ortho = *cameras.get(camera).unwrap().projection().as_orthographic().unwrap();
+            assert_eq!((ortho.left(), ortho.right()), (-50.0, 50.0));
+            assert_eq!((ortho.bottom(), ortho.top()), (-25.0, 25.0));
+
+            let resolution = world.read_storage::<VirtualResolution>().get(camera).unwrap().clone();
+            assert_eq!(resolution.viewport(), (50.0, 0.0, 100.0, 100.0));
+            let transforms = world.read_storage::<UiTransform>();
+            let sizes: Vec<_> = resolution.bars.iter()
+                .map(|bar| {
+                    let transform = transforms.get(*bar).unwrap();
+                    (transform.width, transform.height)
+                })
+                .collect();
+            assert_eq!(sizes, vec![(50.0, 100.0), (50.0, 100.0), (200.0, 0.0), (200.0, 0.0)]);
+        }
+
+        world.write_storage::<VirtualResolution>().get_mut(camera).unwrap().policy = ScalePolicy::Expand;
+        dispatcher.dispatch(&world.res);
+        world.maintain();
+        assert_eq!(world.read_storage::<LetterboxBar>().join().count(), 0);
+        let resolution = world.read_storage::<VirtualResolution>().get(camera).unwrap().clone();
+        assert_eq!(resolution.viewport(), (0.0, 0.0, 200.0, 100.0));
+    }
+}
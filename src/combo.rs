@@ -0,0 +1,342 @@
+use super::*;
+use super::mouse::Mouse;
+use super::replay::InputReplay;
+use serde::{
+    Deserialize, Serialize
+};
+use std::{
+    collections::{
+        HashMap, HashSet, VecDeque
+    },
+    fs::File,
+    io::BufReader,
+    path::Path
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum ComboInput {
+    Key(VirtualKeyCode),
+    Mouse(MouseButton),
+}
+
+// 入力に変化があったフレームの記録
+#[derive(Debug, Clone, PartialEq)]
+pub struct InputSnapshot {
+    pub time: f64,
+    pub held: HashSet<ComboInput>,
+    pub pressed: HashSet<ComboInput>,
+}
+
+pub struct InputHistory {
+    // これより古い記録は捨てる（秒）
+    pub duration: f64,
+    held: HashSet<ComboInput>,
+    snapshots: VecDeque<InputSnapshot>,
+}
+
+impl Default for InputHistory {
+    fn default() -> Self {
+        Self::new(2.0)
+    }
+}
+
+impl InputHistory {
+    pub fn new(duration: f64) -> Self {
+        InputHistory {
+            duration,
+            held: HashSet::new(),
+            snapshots: VecDeque::new(),
+        }
+    }
+
+    pub fn held(&self) -> &HashSet<ComboInput> {
+        &self.held
+    }
+
+    // 古い順
+    pub fn snapshots(&self) -> impl Iterator<Item = &InputSnapshot> {
+        self.snapshots.iter()
+    }
+
+    pub fn last(&self) -> Option<&InputSnapshot> {
+        self.snapshots.back()
+    }
+
+    pub fn clear(&mut self) {
+        self.snapshots.clear();
+    }
+
+    // 押下があったときだけ記録する。戻り値は記録したかどうか
+    fn update(&mut self, time: f64, held: HashSet<ComboInput>) -> bool {
+        let pressed: HashSet<ComboInput> = held.difference(&self.held).cloned().collect();
+        self.held = held;
+        while self.snapshots.front().map_or(false, |s| time - s.time > self.duration) {
+            self.snapshots.pop_front();
+        }
+        if pressed.is_empty() {
+            return false;
+        }
+        self.snapshots.push_back(InputSnapshot {
+            time,
+            held: self.held.clone(),
+            pressed,
+        });
+        true
+    }
+}
+
+fn default_window() -> f32 {
+    0.3
+}
+
+// steps の各要素は同時に押されている入力の組
+// 例: [[Key(Down)], [Key(Down), Key(Right)], [Key(Right), Key(A)]]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Combo {
+    pub name: String,
+    pub steps: Vec<Vec<ComboInput>>,
+    // 前のステップから次のステップまでの最大間隔（秒）
+    #[serde(default = "default_window")]
+    pub window: f32,
+    // true ならステップ以外の入力が押されていると成立しない
+    #[serde(default)]
+    pub exact: bool,
+}
+
+impl Combo {
+    // そのフレームでステップが成立したか（組のうち少なくとも 1 つがそのフレームで押された）
+    fn step_matches(&self, step: &[ComboInput], snapshot: &InputSnapshot) -> bool {
+        step.iter().all(|input| snapshot.held.contains(input))
+            && step.iter().any(|input| snapshot.pressed.contains(input))
+            && (!self.exact || snapshot.held.len() == step.len())
+    }
+
+    // 最新の記録で最後のステップが成立したとき、さかのぼって残りのステップを探す。
+    // 各ステップでは条件を満たす一番新しい記録を選ぶ
+    fn matches(&self, history: &InputHistory, since: f64) -> bool {
+        let (last, rest) = match self.steps.split_last() {
+            Some(split) => split,
+            None => return false,
+        };
+        let mut snapshots = history.snapshots.iter().rev().filter(|s| s.time > since);
+        let mut time = match snapshots.next() {
+            Some(current) if self.step_matches(last, current) => current.time,
+            _ => return false,
+        };
+        for step in rest.iter().rev() {
+            let found = snapshots
+                .by_ref()
+                .take_while(|s| time - s.time <= f64::from(self.window))
+                .find(|s| self.step_matches(step, s));
+            match found {
+                Some(snapshot) => time = snapshot.time,
+                None => return false,
+            }
+        }
+        true
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Combos {
+    pub combos: Vec<Combo>,
+}
+
+impl Combos {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    pub fn with_combo(mut self, combo: Combo) -> Self {
+        self.combos.push(combo);
+        self
+    }
+
+    pub fn load_ron(path: impl AsRef<Path>) -> Result<Self, Error> {
+        let file = File::open(path).map_err(Error::new)?;
+        ron::de::from_reader(BufReader::new(file)).map_err(Error::new)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ComboTriggered {
+    pub name: String,
+    pub time: f64,
+}
+
+pub struct ComboSystem<T: BindingTypes = StringBindings> {
+    // コンボごとに最後に成立した時刻。それ以前の入力は再利用しない
+    triggered: HashMap<String, f64>,
+    _marker: PhantomData<T>,
+}
+
+impl<T: BindingTypes> ComboSystem<T> {
+    pub fn new() -> Self {
+        ComboSystem {
+            triggered: HashMap::new(),
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<T: BindingTypes> Default for ComboSystem<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<'s, T: BindingTypes> System<'s> for ComboSystem<T> {
+    type SystemData = (
+        Write<'s, InputHistory>,
+        Read<'s, Combos>,
+        Read<'s, InputHandler<T>>,
+        Read<'s, Mouse<T>>,
+        Read<'s, Time>,
+        Read<'s, InputReplay>,
+        Write<'s, EventChannel<ComboTriggered>>,
+    );
+
+    fn run(
+        &mut self,
+        (mut history, combos, input, mouse, time, replay, mut combo_events): Self::SystemData
+    ) {
+        let now = replay.absolute_seconds(&time);
+        let held = input
+            .keys_that_are_down()
+            .map(ComboInput::Key)
+            .chain(mouse.buttons().filter(|b| mouse.get(**b)).map(|b| ComboInput::Mouse(*b)))
+            .collect();
+        if !history.update(now, held) {
+            return;
+        }
+        for combo in &combos.combos {
+            let since = self.triggered.get(&combo.name).cloned().unwrap_or(f64::NEG_INFINITY);
+            if combo.matches(&history, since) {
+                self.triggered.insert(combo.name.clone(), now);
+                combo_events.single_write(ComboTriggered {
+                    name: combo.name.clone(),
+                    time: now,
+                });
+            }
+        }
+    }
+}
+
+pub struct ComboBundle<'a, T: BindingTypes = StringBindings> {
+    dep: &'a [&'a str],
+    _marker: PhantomData<T>,
+}
+
+impl<'a, T: BindingTypes> Default for ComboBundle<'a, T> {
+    fn default() -> Self {
+        ComboBundle {
+            dep: &[],
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<'a, T: BindingTypes> ComboBundle<'a, T> {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    pub fn with_dep(mut self, dep: &'a [&'a str]) -> Self {
+        self.dep = dep;
+        self
+    }
+}
+
+// MouseBundle の後に追加すること
+impl<'a, 'b, 'c, T: BindingTypes> SystemBundle<'a, 'b> for ComboBundle<'c, T> {
+    fn build(self, builder: &mut DispatcherBuilder<'a, 'b>) -> Result<(), Error> {
+        let dep = {
+            let mut vec = self.dep.to_vec();
+            vec.push("mouse_system");
+            vec
+        };
+        builder.add(
+            ComboSystem::<T>::new(),
+            "combo_system",
+            &dep,
+        );
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use self::ComboInput::Key;
+
+    fn hadouken(exact: bool) -> Combo {
+        Combo {
+            name: "hadouken".to_string(),
+            steps: vec![
+                vec![Key(VirtualKeyCode::Down)],
+                vec![Key(VirtualKeyCode::Down), Key(VirtualKeyCode::Right)],
+                vec![Key(VirtualKeyCode::Right), Key(VirtualKeyCode::A)],
+            ],
+            window: 0.3,
+            exact,
+        }
+    }
+
+    fn history(frames: &[(f64, &[VirtualKeyCode])]) -> InputHistory {
+        let mut history = InputHistory::new(2.0);
+        for (time, keys) in frames {
+            history.update(*time, keys.iter().map(|key| Key(*key)).collect());
+        }
+        history
+    }
+
+    #[test]
+    fn combo_matches_steps_in_order_within_window() {
+        use amethyst::winit::VirtualKeyCode::{A, Down, Right, Up};
+
+        let input = history(&[
+            (0.0, &[Down]),
+            (0.1, &[Down, Right]),
+            (0.2, &[Right, A]),
+        ]);
+        assert!(hadouken(false).matches(&input, -1.0));
+        assert!(hadouken(true).matches(&input, -1.0));
+        // 前に成立したときより古い入力は使わない
+        assert!(!hadouken(false).matches(&input, 0.0));
+
+        // 間隔が空きすぎている
+        let slow = history(&[
+            (0.0, &[Down]),
+            (0.5, &[Down, Right]),
+            (0.6, &[Right, A]),
+        ]);
+        assert!(!hadouken(false).matches(&slow, -1.0));
+
+        // 最後のステップは最新の記録で成立していなければならない
+        let late = history(&[
+            (0.0, &[Down]),
+            (0.1, &[Down, Right]),
+            (0.2, &[Right, A]),
+            (0.3, &[Right, A, Up]),
+        ]);
+        assert!(!hadouken(false).matches(&late, -1.0));
+
+        // 余計な入力が押されていると exact では成立しない
+        let extra = history(&[
+            (0.0, &[Down, Up]),
+            (0.1, &[Down, Right, Up]),
+            (0.2, &[Right, A, Up]),
+        ]);
+        assert!(hadouken(false).matches(&extra, -1.0));
+        assert!(!hadouken(true).matches(&extra, -1.0));
+
+        // 順番が違う
+        let reversed = history(&[
+            (0.0, &[Right]),
+            (0.1, &[Down, Right]),
+            (0.2, &[Down]),
+            (0.3, &[Right, A]),
+        ]);
+        assert!(!hadouken(false).matches(&reversed, -1.0));
+    }
+}
@@ -0,0 +1,613 @@
+use super::*;
+use super::mouse::Mouse;
+use amethyst::ecs::prelude::{
+    Component, DenseVecStorage, Entities, Entity, WriteStorage
+};
+use std::{
+    cmp::Ordering,
+    collections::HashMap
+};
+
+// 前面に出すときの z の間隔
+const Z_STEP: f32 = 0.001;
+
+// 当たり判定の形（スプライトの中心が原点、ローカル座標）
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum HitShape {
+    Circle(f32),
+    Rect(f32, f32),
+}
+
+impl HitShape {
+    pub fn contains(&self, (x, y): (f32, f32)) -> bool {
+        match *self {
+            HitShape::Circle(radius) => x.hypot(y) <= radius,
+            HitShape::Rect(w, h) => x.abs() <= w * 0.5 && y.abs() <= h * 0.5,
+        }
+    }
+
+    // ワールド座標の点をエンティティのローカル座標に戻して判定する
+    pub fn hit(&self, transform: &Transform, point: (f32, f32)) -> bool {
+        world_to_local(transform, point).map_or(false, |local| self.contains(local))
+    }
+}
+
+// 親を含めたグローバル行列で、ワールド座標の点をローカル座標に戻す
+pub fn world_to_local(transform: &Transform, (x, y): (f32, f32)) -> Option<(f32, f32)> {
+    let inverse = transform.global_matrix().try_inverse()?;
+    let local = inverse * Vector4::new(x, y, global_z(transform), 1.0);
+    Some((local.x, local.y))
+}
+
+// 手前かどうかの比較に使う z（親の分も含む）
+pub fn global_z(transform: &Transform) -> f32 {
+    transform.global_matrix()[(2, 3)]
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DragAxis {
+    Both,
+    X,
+    Y,
+}
+
+pub struct Draggable {
+    pub shape: HitShape,
+    pub button: MouseButton,
+    pub axis: DragAxis,
+    pub grid: Option<(f32, f32)>,
+    pub bounds: Option<((f32, f32), (f32, f32))>,
+    pub bring_to_front: bool,
+    pub group: Option<String>,
+}
+
+impl Component for Draggable {
+    type Storage = DenseVecStorage<Self>;
+}
+
+impl Draggable {
+    pub fn new(shape: HitShape) -> Self {
+        Draggable {
+            shape,
+            button: MouseButton::Left,
+            axis: DragAxis::Both,
+            grid: None,
+            bounds: None,
+            bring_to_front: false,
+            group: None,
+        }
+    }
+
+    pub fn with_button(mut self, button: MouseButton) -> Self {
+        self.button = button;
+        self
+    }
+
+    pub fn with_axis(mut self, axis: DragAxis) -> Self {
+        self.axis = axis;
+        self
+    }
+
+    pub fn with_grid(mut self, w: f32, h: f32) -> Self {
+        self.grid = Some((w, h));
+        self
+    }
+
+    // ワールド座標の左下と右上
+    pub fn with_bounds(mut self, min: (f32, f32), max: (f32, f32)) -> Self {
+        self.bounds = Some((min, max));
+        self
+    }
+
+    pub fn with_bring_to_front(mut self, bring_to_front: bool) -> Self {
+        self.bring_to_front = bring_to_front;
+        self
+    }
+
+    // DropTarget の受け入れ判定に使う
+    pub fn with_group(mut self, group: impl Into<String>) -> Self {
+        self.group = Some(group.into());
+        self
+    }
+
+    pub fn hit(&self, transform: &Transform, point: (f32, f32)) -> bool {
+        self.shape.hit(transform, point)
+    }
+
+    // 軸の固定、グリッドへの吸着、範囲内への制限の順に適用する
+    fn constrain(&self, (x, y): (f32, f32), start: (f32, f32)) -> (f32, f32) {
+        let (mut x, mut y) = match self.axis {
+            DragAxis::Both => (x, y),
+            DragAxis::X => (x, start.1),
+            DragAxis::Y => (start.0, y),
+        };
+        if let Some((w, h)) = self.grid {
+            if w > 0.0 {
+                x = (x / w).round() * w;
+            }
+            if h > 0.0 {
+                y = (y / h).round() * h;
+            }
+        }
+        if let Some((min, max)) = self.bounds {
+            x = x.max(min.0).min(max.0);
+            y = y.max(min.1).min(max.1);
+        }
+        (x, y)
+    }
+}
+
+type AcceptFn = Box<dyn Fn(Entity, &Draggable) -> bool + Send + Sync>;
+
+pub struct DropTarget {
+    pub shape: HitShape,
+    accept: Option<AcceptFn>,
+}
+
+impl Component for DropTarget {
+    type Storage = DenseVecStorage<Self>;
+}
+
+impl DropTarget {
+    // フィルタなしではすべて受け入れる
+    pub fn new(shape: HitShape) -> Self {
+        DropTarget {
+            shape,
+            accept: None,
+        }
+    }
+
+    pub fn with_accept<F>(mut self, accept: F) -> Self
+    where
+        F: Fn(Entity, &Draggable) -> bool + Send + Sync + 'static,
+    {
+        self.accept = Some(Box::new(accept));
+        self
+    }
+
+    pub fn with_accept_group(self, group: impl Into<String>) -> Self {
+        let group = group.into();
+        self.with_accept(move |_, draggable| draggable.group.as_ref() == Some(&group))
+    }
+
+    pub fn accepts(&self, entity: Entity, draggable: &Draggable) -> bool {
+        match self.accept {
+            Some(ref accept) => accept(entity, draggable),
+            None => true,
+        }
+    }
+}
+
+// 拒否された場合はドラッグ開始位置に戻される
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DropEvent {
+    Dropped { entity: Entity, target: Entity },
+    Rejected { entity: Entity, target: Entity },
+}
+
+// ドラッグ中のエンティティに付く
+pub struct Dragging {
+    pub start: (f32, f32),
+    grab: (f32, f32),
+}
+
+impl Component for Dragging {
+    type Storage = DenseVecStorage<Self>;
+}
+
+pub struct DraggableSystem<T: BindingTypes = StringBindings> {
+    _marker: PhantomData<T>,
+}
+
+impl<T: BindingTypes> DraggableSystem<T> {
+    pub fn new() -> Self {
+        DraggableSystem {
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<T: BindingTypes> Default for DraggableSystem<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<'s, T: BindingTypes> System<'s> for DraggableSystem<T> {
+    type SystemData = (
+        Entities<'s>,
+        Read<'s, Mouse<T>>,
+        ReadStorage<'s, Draggable>,
+        ReadStorage<'s, DropTarget>,
+        WriteStorage<'s, Dragging>,
+        WriteStorage<'s, Transform>,
+        Write<'s, EventChannel<DropEvent>>,
+    );
+
+    fn run(
+        &mut self,
+        (
+            entities, mouse, draggables, targets,
+            mut draggings, mut transforms, mut drop_events
+        ): Self::SystemData
+    ) {
+        let cursor = (mouse.x, mouse.y);
+
+        // 押されたボタンごとに一番手前のエンティティをつかむ
+        let mut grabbed: HashMap<MouseButton, (Entity, f32)> = HashMap::new();
+        for (entity, draggable, transform, _) in
+            (&entities, &draggables, &transforms, !&draggings).join()
+        {
+            if !mouse.get_down(draggable.button) || !draggable.hit(transform, cursor) {
+                continue;
+            }
+            let z = global_z(transform);
+            let top = grabbed.entry(draggable.button).or_insert((entity, z));
+            if z > top.1 {
+                *top = (entity, z);
+            }
+        }
+
+        for (entity, _) in grabbed.values() {
+            let start = match transforms.get(*entity) {
+                Some(transform) => (transform.translation().x, transform.translation().y),
+                None => continue,
+            };
+            let dragging = Dragging {
+                start,
+                grab: (start.0 - cursor.0, start.1 - cursor.1),
+            };
+            draggings.insert(*entity, dragging).expect("entity is alive");
+            if draggables.get(*entity).map_or(false, |draggable| draggable.bring_to_front) {
+                bring_to_front(*entity, &entities, &draggables, &mut transforms);
+            }
+        }
+
+        let mut released = Vec::new();
+        let mut dropped = Vec::new();
+        for (entity, draggable, dragging, transform) in
+            (&entities, &draggables, &draggings, &mut transforms).join()
+        {
+            if !mouse.get(draggable.button) && !mouse.get_up(draggable.button) {
+                released.push(entity);
+                continue;
+            }
+            // しきい値を超えるまでは動かさない
+            if mouse.drag(draggable.button).is_some() {
+                let target = (cursor.0 + dragging.grab.0, cursor.1 + dragging.grab.1);
+                let (x, y) = draggable.constrain(target, dragging.start);
+                transform.set_translation_x(x);
+                transform.set_translation_y(y);
+            }
+            if mouse.get_up(draggable.button) {
+                released.push(entity);
+                if mouse.drag(draggable.button).is_some() {
+                    dropped.push((entity, dragging.start));
+                }
+            }
+        }
+        for entity in released {
+            draggings.remove(entity);
+        }
+
+        // 離した位置にある一番手前の DropTarget に渡す
+        for (entity, start) in dropped {
+            let mut top: Option<(Entity, f32)> = None;
+            for (target, drop_target, transform) in (&entities, &targets, &transforms).join() {
+                let z = global_z(transform);
+                if target != entity
+                    && drop_target.shape.hit(transform, cursor)
+                    && top.map_or(true, |(_, top_z)| z > top_z)
+                {
+                    top = Some((target, z));
+                }
+            }
+            let target = match top {
+                Some((target, _)) => target,
+                None => continue,
+            };
+            let accepted = match (targets.get(target), draggables.get(entity)) {
+                (Some(drop_target), Some(draggable)) => drop_target.accepts(entity, draggable),
+                _ => false,
+            };
+            if accepted {
+                drop_events.single_write(DropEvent::Dropped { entity, target });
+            } else {
+                if let Some(transform) = transforms.get_mut(entity) {
+                    transform.set_translation_x(start.0);
+                    transform.set_translation_y(start.1);
+                }
+                drop_events.single_write(DropEvent::Rejected { entity, target });
+            }
+        }
+    }
+}
+
+// bring_to_front なエンティティをグローバルの z 順に詰めて並べ直し、entity を一番手前にする。
+// 親があってもグローバルの z が並ぶように、ローカルの z をその差だけずらす
+fn bring_to_front(
+    entity: Entity,
+    entities: &Entities,
+    draggables: &ReadStorage<Draggable>,
+    transforms: &mut WriteStorage<Transform>,
+) {
+    let mut stack: Vec<(Entity, f32)> = (entities, draggables, &*transforms)
+        .join()
+        .filter(|(_, draggable, _)| draggable.bring_to_front)
+        .map(|(e, _, transform)| (e, global_z(transform)))
+        .collect();
+    let base = stack.iter().map(|(_, z)| *z).fold(f32::INFINITY, f32::min);
+    stack.sort_by(|a, b| a.1.partial_cmp(&b.1).unwrap_or(Ordering::Equal));
+    let position = stack.iter().position(|(e, _)| *e == entity);
+    if let Some(position) = position {
+        let top = stack.remove(position);
+        stack.push(top);
+    }
+    for (i, (e, z)) in stack.iter().enumerate() {
+        if let Some(transform) = transforms.get_mut(*e) {
+            let local_z = transform.translation().z + base + i as f32 * Z_STEP - z;
+            transform.set_translation_z(local_z);
+        }
+    }
+}
+
+pub struct DragBundle<'a, T: BindingTypes = StringBindings> {
+    dep: &'a [&'a str],
+    _marker: PhantomData<T>,
+}
+
+impl<'a, T: BindingTypes> Default for DragBundle<'a, T> {
+    fn default() -> Self {
+        DragBundle {
+            dep: &[],
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<'a, T: BindingTypes> DragBundle<'a, T> {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    pub fn with_dep(mut self, dep: &'a [&'a str]) -> Self {
+        self.dep = dep;
+        self
+    }
+}
+
+// MouseBundle の後に追加すること
+impl<'a, 'b, 'c, T: BindingTypes> SystemBundle<'a, 'b> for DragBundle<'c, T> {
+    fn build(self, builder: &mut DispatcherBuilder<'a, 'b>) -> Result<(), Error> {
+        let dep = {
+            let mut vec = self.dep.to_vec();
+            vec.push("mouse_system");
+            vec
+        };
+        builder.add(
+            DraggableSystem::<T>::new(),
+            "draggable_system",
+            &dep,
+        );
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::mouse::MouseSystem;
+    use amethyst::{
+        core::{
+            Parent, TransformBundle
+        },
+        ecs::prelude::Dispatcher,
+        input::InputSystem,
+        winit::{
+            dpi::LogicalPosition,
+            DeviceId, ModifiersState, WindowId
+        },
+    };
+
+    const HEIGHT: f32 = 200.0;
+
+    fn setup() -> (World, Dispatcher<'static, 'static>) {
+        let mut world = World::new();
+        let mut builder = DispatcherBuilder::new()
+            .with(InputSystem::<StringBindings>::new(None), "input_system", &[])
+            .with(MouseSystem::<StringBindings>::new(), "mouse_system", &["input_system"])
+            .with(DraggableSystem::<StringBindings>::new(), "draggable_system", &["mouse_system"]);
+        TransformBundle::new()
+            .with_dep(&["draggable_system"])
+            .build(&mut builder)
+            .unwrap();
+        let mut dispatcher = builder.build();
+        dispatcher.setup(&mut world.res);
+        world.add_resource(ScreenDimensions::new(200, HEIGHT as u32, 1.0));
+        (world, dispatcher)
+    }
+
+    fn send(world: &mut World, event: WindowEvent) {
+        world.write_resource::<EventChannel<Event>>().single_write(Event::WindowEvent {
+            window_id: unsafe { WindowId::dummy() },
+            event,
+        });
+    }
+
+    // カメラがないので左下原点のワールド座標で指定する
+    fn move_to(world: &mut World, x: f32, y: f32) {
+        send(world, WindowEvent::CursorMoved {
+            device_id: unsafe { DeviceId::dummy() },
+            position: LogicalPosition::new(f64::from(x), f64::from(HEIGHT - y)),
+            modifiers: ModifiersState::default(),
+        });
+    }
+
+    fn left(world: &mut World, state: ElementState) {
+        send(world, WindowEvent::MouseInput {
+            device_id: unsafe { DeviceId::dummy() },
+            state,
+            button: MouseButton::Left,
+            modifiers: ModifiersState::default(),
+        });
+    }
+
+    fn frame(world: &mut World, dispatcher: &mut Dispatcher) {
+        dispatcher.dispatch(&world.res);
+        world.maintain();
+    }
+
+    fn spawn(world: &mut World, draggable: Draggable, (x, y, z): (f32, f32, f32)) -> Entity {
+        world.create_entity().with(draggable).with(Transform::from_xyz(x, y, z)).build()
+    }
+
+    fn position(world: &World, entity: Entity) -> (f32, f32, f32) {
+        let transforms = world.read_storage::<Transform>();
+        let translation = transforms.get(entity).unwrap().translation();
+        (translation.x, translation.y, translation.z)
+    }
+
+    // (x, y) で押して (to_x, to_y) まで動かし、離さずに返す
+    fn drag(world: &mut World, dispatcher: &mut Dispatcher, (x, y): (f32, f32), (to_x, to_y): (f32, f32)) {
+        frame(world, dispatcher);
+        move_to(world, x, y);
+        left(world, ElementState::Pressed);
+        frame(world, dispatcher);
+        move_to(world, to_x, to_y);
+        frame(world, dispatcher);
+    }
+
+    #[test]
+    fn constrain_locks_snaps_and_clamps() {
+        let start = (100.0, 100.0);
+        let draggable = Draggable::new(HitShape::Circle(10.0));
+        assert_eq!(draggable.constrain((130.0, 120.0), start), (130.0, 120.0));
+
+        let x_only = Draggable::new(HitShape::Circle(10.0)).with_axis(DragAxis::X);
+        assert_eq!(x_only.constrain((130.0, 120.0), start), (130.0, 100.0));
+        let y_only = Draggable::new(HitShape::Circle(10.0)).with_axis(DragAxis::Y);
+        assert_eq!(y_only.constrain((130.0, 120.0), start), (100.0, 120.0));
+
+        let grid = Draggable::new(HitShape::Circle(10.0)).with_grid(16.0, 10.0);
+        assert_eq!(grid.constrain((121.0, 107.0), start), (128.0, 110.0));
+        // 0 の軸は吸着しない
+        let no_y_grid = Draggable::new(HitShape::Circle(10.0)).with_grid(16.0, 0.0);
+        assert_eq!(no_y_grid.constrain((121.0, 107.0), start), (128.0, 107.0));
+
+        let bounded = Draggable::new(HitShape::Circle(10.0)).with_bounds((0.0, 50.0), (150.0, 150.0));
+        assert_eq!(bounded.constrain((190.0, 20.0), start), (150.0, 50.0));
+
+        // 吸着した結果が範囲外なら範囲内に戻す
+        let both = Draggable::new(HitShape::Circle(10.0))
+            .with_axis(DragAxis::X)
+            .with_grid(40.0, 40.0)
+            .with_bounds((0.0, 0.0), (150.0, 150.0));
+        assert_eq!(both.constrain((145.0, 10.0), start), (150.0, 120.0));
+    }
+
+    #[test]
+    fn hit_shapes_use_the_global_transform() {
+        assert!(HitShape::Circle(10.0).contains((6.0, 8.0)));
+        assert!(!HitShape::Circle(10.0).contains((6.0, 8.1)));
+        assert!(HitShape::Rect(20.0, 10.0).contains((10.0, -5.0)));
+        assert!(!HitShape::Rect(20.0, 10.0).contains((10.0, 5.1)));
+
+        let (mut world, mut dispatcher) = setup();
+        let parent = world.create_entity().with(Transform::from_xyz(50.0, 0.0, 1.0)).build();
+        let mut local = Transform::from_xyz(10.0, 20.0, 0.5);
+        local.set_scale(Vector3::new(2.0, 2.0, 1.0));
+        let child = world
+            .create_entity()
+            .with(local)
+            .with(Parent { entity: parent })
+            .build();
+        frame(&mut world, &mut dispatcher);
+
+        let transforms = world.read_storage::<Transform>();
+        let transform = transforms.get(child).unwrap();
+        assert_eq!(global_z(transform), 1.5);
+        // 親の位置と子の拡大を含めて戻す
+        assert_eq!(world_to_local(transform, (64.0, 24.0)), Some((2.0, 2.0)));
+        assert!(HitShape::Rect(10.0, 10.0).hit(transform, (69.0, 29.0)));
+        assert!(!HitShape::Rect(10.0, 10.0).hit(transform, (71.0, 20.0)));
+    }
+
+    #[test]
+    fn drag_grabs_the_topmost_entity_and_brings_it_to_front() {
+        let (mut world, mut dispatcher) = setup();
+        let draggable = || Draggable::new(HitShape::Circle(20.0)).with_bring_to_front(true);
+        let front = spawn(&mut world, draggable(), (100.0, 100.0, 0.5));
+        // ローカルの z は小さいが、親の分を含めると一番手前
+        let parent = world.create_entity().with(Transform::from_xyz(0.0, 0.0, 1.0)).build();
+        let child = world
+            .create_entity()
+            .with(draggable())
+            .with(Transform::from_xyz(105.0, 100.0, 0.0))
+            .with(Parent { entity: parent })
+            .build();
+
+        drag(&mut world, &mut dispatcher, (102.0, 100.0), (132.0, 110.0));
+        assert_eq!(position(&world, front), (100.0, 100.0, 0.5));
+        let (x, y, _) = position(&world, child);
+        assert_eq!((x, y), (135.0, 110.0));
+
+        // 離したら止まる
+        left(&mut world, ElementState::Released);
+        frame(&mut world, &mut dispatcher);
+        move_to(&mut world, 180.0, 180.0);
+        frame(&mut world, &mut dispatcher);
+        let (x, y, _) = position(&world, child);
+        assert_eq!((x, y), (135.0, 110.0));
+
+        // 後ろのものを掴むとグローバルの z で一番手前に並べ直される
+        drag(&mut world, &mut dispatcher, (90.0, 100.0), (60.0, 100.0));
+        left(&mut world, ElementState::Released);
+        frame(&mut world, &mut dispatcher);
+        let transforms = world.read_storage::<Transform>();
+        let front_z = global_z(transforms.get(front).unwrap());
+        let child_z = global_z(transforms.get(child).unwrap());
+        assert!(front_z > child_z, "{} <= {}", front_z, child_z);
+        assert!((front_z - child_z - Z_STEP).abs() < 1e-6);
+        assert_eq!(position(&world, front).0, 70.0);
+    }
+
+    #[test]
+    fn drop_is_accepted_or_snaps_back() {
+        let (mut world, mut dispatcher) = setup();
+        let mut reader = world.write_resource::<EventChannel<DropEvent>>().register_reader();
+        let card = spawn(
+            &mut world,
+            Draggable::new(HitShape::Rect(20.0, 20.0)).with_group("card"),
+            (20.0, 20.0, 1.0),
+        );
+        let coins = world
+            .create_entity()
+            .with(DropTarget::new(HitShape::Rect(40.0, 40.0)).with_accept_group("coin"))
+            .with(Transform::from_xyz(100.0, 20.0, 0.0))
+            .build();
+        let hand = world
+            .create_entity()
+            .with(DropTarget::new(HitShape::Rect(40.0, 40.0)).with_accept_group("card"))
+            .with(Transform::from_xyz(100.0, 100.0, 0.0))
+            .build();
+
+        drag(&mut world, &mut dispatcher, (20.0, 20.0), (105.0, 25.0));
+        assert_eq!(position(&world, card), (105.0, 25.0, 1.0));
+        left(&mut world, ElementState::Released);
+        frame(&mut world, &mut dispatcher);
+        assert_eq!(position(&world, card), (20.0, 20.0, 1.0));
+        {
+            let events = world.read_resource::<EventChannel<DropEvent>>();
+            let events: Vec<DropEvent> = events.read(&mut reader).cloned().collect();
+            assert_eq!(events, vec![DropEvent::Rejected { entity: card, target: coins }]);
+        }
+
+        drag(&mut world, &mut dispatcher, (20.0, 20.0), (95.0, 90.0));
+        left(&mut world, ElementState::Released);
+        frame(&mut world, &mut dispatcher);
+        assert_eq!(position(&world, card), (95.0, 90.0, 1.0));
+        let events = world.read_resource::<EventChannel<DropEvent>>();
+        let events: Vec<DropEvent> = events.read(&mut reader).cloned().collect();
+        assert_eq!(events, vec![DropEvent::Dropped { entity: card, target: hand }]);
+    }
+}
@@ -0,0 +1,643 @@
+use super::*;
+use super::replay::InputReplay;
+use amethyst::input::{
+    Button, ControllerAxis, ControllerButton, ControllerEvent, InputEvent
+};
+use std::collections::{
+    hash_map::Entry, HashMap
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stick {
+    Left,
+    Right,
+}
+
+impl Stick {
+    fn axes(self) -> (ControllerAxis, ControllerAxis) {
+        match self {
+            Stick::Left => (ControllerAxis::LeftX, ControllerAxis::LeftY),
+            Stick::Right => (ControllerAxis::RightX, ControllerAxis::RightY),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum GamepadEvent {
+    Connected(u32),
+    Disconnected(u32),
+    Pressed { id: u32, button: ControllerButton },
+    Released { id: u32, button: ControllerButton },
+}
+
+// コントローラ 1 台分。ボタンと軸の型が Hash を実装していないので Vec で持つ
+#[derive(Debug, Clone, Default)]
+pub struct Gamepad {
+    state: Vec<ControllerButton>,
+    press: Vec<ControllerButton>,
+    release: Vec<ControllerButton>,
+    // -1.0 ~ 1.0 の生の値
+    axes: Vec<(ControllerAxis, f32)>,
+    stick_dead_zone: f32,
+    trigger_dead_zone: f32,
+}
+
+impl Gamepad {
+    pub fn get(&self, button: ControllerButton) -> bool {
+        self.state.contains(&button)
+    }
+
+    pub fn get_down(&self, button: ControllerButton) -> bool {
+        self.press.contains(&button)
+    }
+
+    pub fn get_up(&self, button: ControllerButton) -> bool {
+        self.release.contains(&button)
+    }
+
+    pub fn buttons(&self) -> impl Iterator<Item = &ControllerButton> {
+        self.state.iter()
+    }
+
+    pub fn raw_axis(&self, axis: ControllerAxis) -> f32 {
+        self.axes
+            .iter()
+            .find(|(a, _)| *a == axis)
+            .map_or(0.0, |(_, value)| *value)
+    }
+
+    // スティックの軸は 2 軸まとめてデッドゾーンを適用した値
+    pub fn axis(&self, axis: ControllerAxis) -> f32 {
+        match axis {
+            ControllerAxis::LeftX => self.stick(Stick::Left).0,
+            ControllerAxis::LeftY => self.stick(Stick::Left).1,
+            ControllerAxis::RightX => self.stick(Stick::Right).0,
+            ControllerAxis::RightY => self.stick(Stick::Right).1,
+            ControllerAxis::LeftTrigger | ControllerAxis::RightTrigger => self.trigger(axis),
+        }
+    }
+
+    // SDL と同じく y は下が正
+    pub fn stick(&self, stick: Stick) -> (f32, f32) {
+        let (x_axis, y_axis) = stick.axes();
+        let (x, y) = (self.raw_axis(x_axis), self.raw_axis(y_axis));
+        let length = x.hypot(y);
+        if length <= self.stick_dead_zone || length == 0.0 {
+            return (0.0, 0.0);
+        }
+        // デッドゾーンの外側を 0.0 ~ 1.0 に引き伸ばす
+        let scaled = ((length - self.stick_dead_zone) / (1.0 - self.stick_dead_zone)).min(1.0);
+        (x / length * scaled, y / length * scaled)
+    }
+
+    fn trigger(&self, axis: ControllerAxis) -> f32 {
+        let value = self.raw_axis(axis).abs();
+        if value <= self.trigger_dead_zone {
+            0.0
+        } else {
+            ((value - self.trigger_dead_zone) / (1.0 - self.trigger_dead_zone)).min(1.0)
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct Gamepads {
+    pads: HashMap<u32, Gamepad>,
+    events: Vec<GamepadEvent>,
+}
+
+impl Gamepads {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    pub fn get_pad(&self, id: u32) -> Option<&Gamepad> {
+        self.pads.get(&id)
+    }
+
+    pub fn is_connected(&self, id: u32) -> bool {
+        self.pads.contains_key(&id)
+    }
+
+    pub fn connected(&self) -> impl Iterator<Item = u32> + '_ {
+        self.pads.keys().cloned()
+    }
+
+    // 一番小さい id のコントローラ。1 人用のゲームならこれで足りる
+    pub fn first(&self) -> Option<&Gamepad> {
+        self.pads.keys().min().and_then(|id| self.pads.get(id))
+    }
+
+    pub fn get(&self, id: u32, button: ControllerButton) -> bool {
+        self.pads.get(&id).map_or(false, |pad| pad.get(button))
+    }
+
+    pub fn get_down(&self, id: u32, button: ControllerButton) -> bool {
+        self.pads.get(&id).map_or(false, |pad| pad.get_down(button))
+    }
+
+    pub fn get_up(&self, id: u32, button: ControllerButton) -> bool {
+        self.pads.get(&id).map_or(false, |pad| pad.get_up(button))
+    }
+
+    pub fn axis(&self, id: u32, axis: ControllerAxis) -> f32 {
+        self.pads.get(&id).map_or(0.0, |pad| pad.axis(axis))
+    }
+
+    pub fn stick(&self, id: u32, stick: Stick) -> (f32, f32) {
+        self.pads.get(&id).map_or((0.0, 0.0), |pad| pad.stick(stick))
+    }
+
+    // どれか 1 台でも押されていれば true
+    pub fn any(&self, button: ControllerButton) -> bool {
+        self.pads.values().any(|pad| pad.get(button))
+    }
+
+    pub fn any_down(&self, button: ControllerButton) -> bool {
+        self.pads.values().any(|pad| pad.get_down(button))
+    }
+
+    pub fn any_up(&self, button: ControllerButton) -> bool {
+        self.pads.values().any(|pad| pad.get_up(button))
+    }
+
+    // 接続状態とボタンは InputHandler から取る（接続・切断は InputEvent として流れてこない）
+    fn state_update<T: BindingTypes>(
+        &mut self,
+        input: &InputHandler<T>,
+        stick_dead_zone: f32,
+        trigger_dead_zone: f32,
+    ) {
+        let connected: Vec<u32> = input.connected_controllers().collect();
+        let disconnected: Vec<u32> = self
+            .pads
+            .keys()
+            .filter(|id| !connected.contains(id))
+            .cloned()
+            .collect();
+        for id in disconnected {
+            self.pads.remove(&id);
+            self.events.push(GamepadEvent::Disconnected(id));
+        }
+        for id in connected {
+            if let Entry::Vacant(entry) = self.pads.entry(id) {
+                entry.insert(Gamepad::default());
+                self.events.push(GamepadEvent::Connected(id));
+            }
+        }
+
+        for (id, pad) in self.pads.iter_mut() {
+            pad.stick_dead_zone = stick_dead_zone;
+            pad.trigger_dead_zone = trigger_dead_zone;
+            let down: Vec<ControllerButton> = input
+                .controller_buttons_that_are_down()
+                .filter(|(pad_id, _)| pad_id == id)
+                .map(|(_, button)| *button)
+                .collect();
+            pad.press = down.iter().filter(|b| !pad.state.contains(b)).cloned().collect();
+            pad.release = pad.state.iter().filter(|b| !down.contains(b)).cloned().collect();
+            pad.state = down;
+            for button in &pad.press {
+                self.events.push(GamepadEvent::Pressed { id: *id, button: *button });
+            }
+            for button in &pad.release {
+                self.events.push(GamepadEvent::Released { id: *id, button: *button });
+            }
+        }
+    }
+
+    // InputHandler は軸のイベントに SDL の番号をそのまま載せるので、ControllerIds で id に直す
+    fn axis_update<T: BindingTypes>(&mut self, event: &InputEvent<T>, ids: &mut ControllerIds) {
+        if let InputEvent::ControllerAxisMoved { which, axis, value } = *event {
+            if let Some(pad) = ids.id(which).and_then(|id| self.pads.get_mut(&id)) {
+                let value = value.clamp(-1.0, 1.0);
+                match pad.axes.iter_mut().find(|(a, _)| *a == axis) {
+                    Some(entry) => entry.1 = value,
+                    None => pad.axes.push((axis, value)),
+                }
+            }
+        }
+    }
+}
+
+// SDL の番号（InputEvent の which）から InputHandler の id への対応表。
+// InputHandler は空いている一番小さい id を振り直すが、SDL の番号は再利用されないので、
+// 抜き差しすると両者はずれる。InputHandler の対応表は外から見えないので、
+// ボタンのイベントの組（生のイベントの直後に id つきの ButtonPressed が流れる）と
+// 接続中の id の増減から組み立てる
+#[derive(Debug, Default)]
+pub struct ControllerIds {
+    // (id, which)
+    ids: Vec<(u32, u32)>,
+    // 接続されたが which がまだ分からない id（小さい順）
+    unknown: Vec<u32>,
+    // 切断されたコントローラの which。SDL は同じ番号を使い回さない
+    retired: Vec<u32>,
+    last_button: Option<(u32, ControllerButton)>,
+}
+
+impl ControllerIds {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    // 接続中の id を InputHandler に合わせる
+    pub fn sync<T: BindingTypes>(&mut self, input: &InputHandler<T>) {
+        let connected: Vec<u32> = input.connected_controllers().collect();
+        let retired = &mut self.retired;
+        self.ids.retain(|(id, which)| {
+            let keep = connected.contains(id);
+            if !keep {
+                retired.push(*which);
+            }
+            keep
+        });
+        self.unknown.retain(|id| connected.contains(id));
+        for id in &connected {
+            let known = self.ids.iter().any(|(i, _)| i == id);
+            if !known && !self.unknown.contains(id) {
+                self.unknown.push(*id);
+            }
+        }
+        self.unknown.sort();
+        self.last_button = None;
+    }
+
+    // InputEvent を流れてきた順に渡す
+    pub fn observe<T: BindingTypes>(&mut self, event: &InputEvent<T>) {
+        let last = self.last_button.take();
+        match *event {
+            InputEvent::ControllerButtonPressed { which, button }
+            | InputEvent::ControllerButtonReleased { which, button } => {
+                self.last_button = Some((which, button));
+            }
+            InputEvent::ButtonPressed(Button::Controller(id, button))
+            | InputEvent::ButtonReleased(Button::Controller(id, button)) => {
+                if let Some((which, b)) = last {
+                    if b == button {
+                        self.learn(which, id);
+                    }
+                }
+            }
+            _ => (),
+        }
+    }
+
+    pub fn id(&mut self, which: u32) -> Option<u32> {
+        if let Some(&(id, _)) = self.ids.iter().find(|(_, w)| *w == which) {
+            return Some(id);
+        }
+        // 知らない番号は新しく接続されたコントローラのもの。
+        // 同じフレームに何台も接続されたときは、ボタンの組が見つかるまで小さい id から仮に割り当てる
+        if self.retired.contains(&which) || self.unknown.is_empty() {
+            return None;
+        }
+        let id = self.unknown[0];
+        self.learn(which, id);
+        Some(id)
+    }
+
+    pub(crate) fn learn(&mut self, which: u32, id: u32) {
+        // 仮に割り当てていたものは外して、もう一度わからない扱いに戻す
+        let unknown = &mut self.unknown;
+        self.ids.retain(|&(i, w)| {
+            let keep = i != id && w != which;
+            if !keep && i != id {
+                unknown.push(i);
+            }
+            keep
+        });
+        self.ids.push((id, which));
+        self.unknown.retain(|i| *i != id);
+        self.unknown.sort();
+    }
+}
+
+pub struct GamepadSystem<T: BindingTypes = StringBindings> {
+    reader: Option<ReaderId<InputEvent<T>>>,
+    stick_dead_zone: f32,
+    trigger_dead_zone: f32,
+}
+
+impl<T: BindingTypes> GamepadSystem<T> {
+    pub fn new() -> Self {
+        GamepadSystem {
+            reader: None,
+            stick_dead_zone: 0.2,
+            trigger_dead_zone: 0.1,
+        }
+    }
+
+    pub fn with_dead_zone(mut self, stick: f32, trigger: f32) -> Self {
+        self.stick_dead_zone = stick;
+        self.trigger_dead_zone = trigger;
+        self
+    }
+}
+
+impl<T: BindingTypes> Default for GamepadSystem<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<'s, T: BindingTypes> System<'s> for GamepadSystem<T> {
+    type SystemData = (
+        Write<'s, Gamepads>,
+        Write<'s, ControllerIds>,
+        Read<'s, InputHandler<T>>,
+        Read<'s, EventChannel<InputEvent<T>>>,
+        Read<'s, InputReplay>,
+        Write<'s, EventChannel<GamepadEvent>>,
+    );
+
+    fn run(
+        &mut self,
+        (mut gamepads, mut ids, input, input_events, replay, mut gamepad_events): Self::SystemData
+    ) {
+        gamepads.state_update(&input, self.stick_dead_zone, self.trigger_dead_zone);
+        ids.sync(&input);
+        let reader = self.reader.as_mut().expect("GamepadSystem::setup was not called");
+        let events: Vec<&InputEvent<T>> = input_events.read(reader).collect();
+        for event in replay.input_events(&events) {
+            ids.observe(event);
+            gamepads.axis_update(event, &mut ids);
+        }
+        gamepad_events.drain_vec_write(&mut gamepads.events);
+    }
+
+    fn setup(&mut self, res: &mut Resources) {
+        Self::SystemData::setup(res);
+        self.reader = Some(res.fetch_mut::<EventChannel<InputEvent<T>>>().register_reader());
+    }
+}
+
+pub struct GamepadBundle<'a, T: BindingTypes = StringBindings> {
+    dep: &'a [&'a str],
+    stick_dead_zone: f32,
+    trigger_dead_zone: f32,
+    _marker: PhantomData<T>,
+}
+
+impl<'a, T: BindingTypes> Default for GamepadBundle<'a, T> {
+    fn default() -> Self {
+        GamepadBundle {
+            dep: &[],
+            stick_dead_zone: 0.2,
+            trigger_dead_zone: 0.1,
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<'a, T: BindingTypes> GamepadBundle<'a, T> {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    pub fn with_dep(mut self, dep: &'a [&'a str]) -> Self {
+        self.dep = dep;
+        self
+    }
+
+    // スティックは半径、トリガーは押し込み量（どちらも 0.0 ~ 1.0）
+    pub fn with_dead_zone(mut self, stick: f32, trigger: f32) -> Self {
+        self.stick_dead_zone = stick;
+        self.trigger_dead_zone = trigger;
+        self
+    }
+}
+
+impl<'a, 'b, 'c, T: BindingTypes> SystemBundle<'a, 'b> for GamepadBundle<'c, T> {
+    fn build(self, builder: &mut DispatcherBuilder<'a, 'b>) -> Result<(), Error> {
+        let dep = {
+            let mut vec = self.dep.to_vec();
+            vec.push("input_system");
+            vec
+        };
+        builder.add(
+            GamepadSystem::<T>::new().with_dead_zone(self.stick_dead_zone, self.trigger_dead_zone),
+            "gamepad_system",
+            &dep,
+        );
+        Ok(())
+    }
+}
+
+// 実機なしで動かすための仮想コントローラ。
+// SDL の代わりに InputHandler::send_controller_event へ流すので、InputHandler 側の状態も更新される
+#[derive(Default)]
+pub struct GamepadSimulator {
+    queue: Vec<ControllerEvent>,
+}
+
+impl GamepadSimulator {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    // which は SDL 側の番号で、実機と同じく再利用しないこと。
+    // Gamepads で使う id は InputHandler が空いている一番小さい番号を振る。
+    // 接続前のコントローラの入力は InputHandler に捨てられる
+    pub fn connect(&mut self, which: u32) -> &mut Self {
+        self.push(ControllerEvent::ControllerConnected { which })
+    }
+
+    pub fn disconnect(&mut self, which: u32) -> &mut Self {
+        self.push(ControllerEvent::ControllerDisconnected { which })
+    }
+
+    pub fn press(&mut self, which: u32, button: ControllerButton) -> &mut Self {
+        self.push(ControllerEvent::ControllerButtonPressed { which, button })
+    }
+
+    pub fn release(&mut self, which: u32, button: ControllerButton) -> &mut Self {
+        self.push(ControllerEvent::ControllerButtonReleased { which, button })
+    }
+
+    pub fn move_axis(&mut self, which: u32, axis: ControllerAxis, value: f32) -> &mut Self {
+        self.push(ControllerEvent::ControllerAxisMoved { which, axis, value })
+    }
+
+    pub fn move_stick(&mut self, which: u32, stick: Stick, x: f32, y: f32) -> &mut Self {
+        let (x_axis, y_axis) = stick.axes();
+        self.move_axis(which, x_axis, x).move_axis(which, y_axis, y)
+    }
+
+    fn push(&mut self, event: ControllerEvent) -> &mut Self {
+        self.queue.push(event);
+        self
+    }
+}
+
+pub struct GamepadSimulatorSystem<T: BindingTypes = StringBindings> {
+    _marker: PhantomData<T>,
+}
+
+impl<T: BindingTypes> GamepadSimulatorSystem<T> {
+    pub fn new() -> Self {
+        GamepadSimulatorSystem {
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<T: BindingTypes> Default for GamepadSimulatorSystem<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<'s, T: BindingTypes> System<'s> for GamepadSimulatorSystem<T> {
+    type SystemData = (
+        Write<'s, GamepadSimulator>,
+        Write<'s, ControllerIds>,
+        Write<'s, InputHandler<T>>,
+        Write<'s, EventChannel<InputEvent<T>>>,
+    );
+
+    fn run(&mut self, (mut simulator, mut ids, mut input, mut input_events): Self::SystemData) {
+        for event in simulator.queue.drain(..) {
+            let before: Vec<u32> = input.connected_controllers().collect();
+            input.send_controller_event(&event, &mut input_events);
+            // 自分で接続したものは対応がはっきり分かる
+            if let ControllerEvent::ControllerConnected { which } = event {
+                if let Some(id) = input.connected_controllers().find(|id| !before.contains(id)) {
+                    ids.learn(which, id);
+                }
+            }
+        }
+    }
+}
+
+pub struct GamepadSimulatorBundle<'a, T: BindingTypes = StringBindings> {
+    dep: &'a [&'a str],
+    _marker: PhantomData<T>,
+}
+
+impl<'a, T: BindingTypes> Default for GamepadSimulatorBundle<'a, T> {
+    fn default() -> Self {
+        GamepadSimulatorBundle {
+            dep: &[],
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<'a, T: BindingTypes> GamepadSimulatorBundle<'a, T> {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    pub fn with_dep(mut self, dep: &'a [&'a str]) -> Self {
+        self.dep = dep;
+        self
+    }
+}
+
+// GamepadBundle より前に追加すること
+impl<'a, 'b, 'c, T: BindingTypes> SystemBundle<'a, 'b> for GamepadSimulatorBundle<'c, T> {
+    fn build(self, builder: &mut DispatcherBuilder<'a, 'b>) -> Result<(), Error> {
+        let dep = {
+            let mut vec = self.dep.to_vec();
+            vec.push("input_system");
+            vec
+        };
+        builder.add(
+            GamepadSimulatorSystem::<T>::new(),
+            "gamepad_simulator_system",
+            &dep,
+        );
+        Ok(())
+    }
+}
+
+pub fn initialise_gamepads(world: &mut World) {
+    world.add_resource(Gamepads::new());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pad(x: f32, y: f32) -> Gamepad {
+        Gamepad {
+            state: Vec::new(),
+            press: Vec::new(),
+            release: Vec::new(),
+            axes: vec![(ControllerAxis::LeftX, x), (ControllerAxis::LeftY, y)],
+            stick_dead_zone: 0.2,
+            trigger_dead_zone: 0.1,
+        }
+    }
+
+    #[test]
+    fn stick_applies_radial_dead_zone() {
+        assert_eq!(pad(0.0, 0.0).stick(Stick::Left), (0.0, 0.0));
+        // 2 軸合わせた長さで判定するので、斜めでも内側なら 0
+        assert_eq!(pad(0.14, 0.14).stick(Stick::Left), (0.0, 0.0));
+        assert_eq!(pad(0.2, 0.0).stick(Stick::Left), (0.0, 0.0));
+        assert_eq!(pad(0.0, 0.0).stick(Stick::Right), (0.0, 0.0));
+
+        // 外側は 0.0 ~ 1.0 に引き伸ばされ、向きは変わらない
+        let (x, y) = pad(0.6, 0.0).stick(Stick::Left);
+        assert!((x - 0.5).abs() < 1e-6 && y == 0.0);
+        let (x, y) = pad(-0.3, 0.4).stick(Stick::Left);
+        let length = x.hypot(y);
+        assert!((length - 0.375).abs() < 1e-6);
+        assert!((x / length + 0.6).abs() < 1e-6 && (y / length - 0.8).abs() < 1e-6);
+        assert!((pad(0.0, -0.6).axis(ControllerAxis::LeftY) + 0.5).abs() < 1e-6);
+
+        // 斜めに倒しきっても 1.0 を超えない
+        let (x, y) = pad(1.0, 1.0).stick(Stick::Left);
+        assert!((x.hypot(y) - 1.0).abs() < 1e-6);
+    }
+
+    fn send(
+        input: &mut InputHandler<StringBindings>,
+        events: &mut EventChannel<InputEvent<StringBindings>>,
+        event: ControllerEvent,
+    ) {
+        input.send_controller_event(&event, events);
+    }
+
+    #[test]
+    fn axis_follows_reconnected_pad() {
+        let mut input = InputHandler::<StringBindings>::new();
+        let mut events = EventChannel::new();
+        let mut reader = events.register_reader();
+        let mut ids = ControllerIds::new();
+
+        send(&mut input, &mut events, ControllerEvent::ControllerConnected { which: 0 });
+        send(&mut input, &mut events, ControllerEvent::ControllerConnected { which: 1 });
+        ids.sync(&input);
+        send(&mut input, &mut events, ControllerEvent::ControllerButtonPressed {
+            which: 1,
+            button: ControllerButton::A,
+        });
+        for event in events.read(&mut reader) {
+            ids.observe(event);
+        }
+        assert_eq!(ids.id(1), Some(1));
+
+        // 0 を抜いて挿し直すと、SDL は新しい番号 2 を、InputHandler は空いた id 0 を使う
+        send(&mut input, &mut events, ControllerEvent::ControllerDisconnected { which: 0 });
+        send(&mut input, &mut events, ControllerEvent::ControllerConnected { which: 2 });
+        send(&mut input, &mut events, ControllerEvent::ControllerAxisMoved {
+            which: 2,
+            axis: ControllerAxis::LeftX,
+            value: 0.5,
+        });
+        ids.sync(&input);
+        let mut moved = Vec::new();
+        for event in events.read(&mut reader) {
+            ids.observe(event);
+            if let InputEvent::ControllerAxisMoved { which, .. } = *event {
+                moved.push(ids.id(which));
+            }
+        }
+        assert_eq!(moved, vec![Some(0)]);
+        assert_eq!(ids.id(1), Some(1));
+        assert_eq!(ids.id(0), None);
+    }
+}
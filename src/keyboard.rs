@@ -0,0 +1,224 @@
+use super::*;
+use std::collections::HashSet;
+
+pub struct Keyboard<T: BindingTypes = StringBindings> {
+    state: HashSet<VirtualKeyCode>,
+    press: HashSet<VirtualKeyCode>,
+    release: HashSet<VirtualKeyCode>,
+
+    _marker: PhantomData<T>,
+}
+
+impl<T: BindingTypes> Default for Keyboard<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T: BindingTypes> Keyboard<T> {
+    pub fn new() -> Self {
+        Keyboard {
+            state: HashSet::new(),
+            press: HashSet::new(),
+            release: HashSet::new(),
+            _marker: PhantomData,
+        }
+    }
+
+    pub fn get(&self, key: VirtualKeyCode) -> bool {
+        self.state.contains(&key)
+    }
+
+    pub fn get_down(&self, key: VirtualKeyCode) -> bool {
+        self.press.contains(&key)
+    }
+
+    pub fn get_up(&self, key: VirtualKeyCode) -> bool {
+        self.release.contains(&key)
+    }
+
+    pub fn keys(&self) -> impl Iterator<Item = &VirtualKeyCode> {
+        self.state.iter()
+    }
+
+    fn state_update(&mut self, input: &InputHandler<T>) {
+        let down_keys: HashSet<VirtualKeyCode> = input.keys_that_are_down().collect();
+        self.press = down_keys.difference(&self.state).cloned().collect();
+        self.release = self.state.difference(&down_keys).cloned().collect();
+        self.state = down_keys;
+    }
+}
+
+pub struct KeyboardSystem<T: BindingTypes = StringBindings> {
+    _marker: PhantomData<T>,
+}
+
+impl<T: BindingTypes> KeyboardSystem<T> {
+    pub fn new() -> Self {
+        KeyboardSystem {
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<T: BindingTypes> Default for KeyboardSystem<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<'s, T: BindingTypes> System<'s> for KeyboardSystem<T> {
+    type SystemData = (
+        Write<'s, Keyboard<T>>,
+        Read<'s, InputHandler<T>>
+    );
+
+    fn run(&mut self, (mut keyboard, input): Self::SystemData) {
+        keyboard.state_update(&input);
+    }
+}
+
+pub struct KeyboardBundle<'a, T: BindingTypes = StringBindings> {
+    dep: &'a [&'a str],
+    _marker: PhantomData<T>,
+}
+
+impl<'a, T: BindingTypes> Default for KeyboardBundle<'a, T> {
+    fn default() -> Self {
+        KeyboardBundle {
+            dep: &[],
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<'a, T: BindingTypes> KeyboardBundle<'a, T> {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    pub fn with_dep(mut self, dep: &'a [&'a str]) -> Self {
+        self.dep = dep;
+        self
+    }
+}
+
+impl<'a, 'b, 'c, T: BindingTypes> SystemBundle<'a, 'b> for KeyboardBundle<'c, T> {
+    fn build(self, builder: &mut DispatcherBuilder<'a, 'b>) -> Result<(), Error> {
+        let dep = {
+            let mut vec = self.dep.to_vec();
+            vec.push("input_system");
+            vec
+        };
+        builder.add(
+            KeyboardSystem::<T>::new(),
+            "keyboard_system",
+            &dep,
+        );
+        Ok(())
+    }
+}
+
+pub fn initialise_keyboard(world: &mut World) {
+    world.add_resource(Keyboard::<StringBindings>::new());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use amethyst::{
+        core::shrev::EventChannel,
+        winit::{
+            DeviceId, KeyboardInput, ModifiersState, WindowId
+        },
+    };
+
+    #[derive(Debug)]
+    struct TestBindings;
+
+    impl BindingTypes for TestBindings {
+        type Axis = u8;
+        type Action = u8;
+    }
+
+    fn send<T: BindingTypes>(input: &mut InputHandler<T>, key: VirtualKeyCode, state: ElementState) {
+        let event = Event::WindowEvent {
+            window_id: unsafe { WindowId::dummy() },
+            event: WindowEvent::KeyboardInput {
+                device_id: unsafe { DeviceId::dummy() },
+                input: KeyboardInput {
+                    scancode: 0,
+                    state,
+                    virtual_keycode: Some(key),
+                    modifiers: ModifiersState::default(),
+                },
+            },
+        };
+        input.send_event(&event, &mut EventChannel::new(), 1.0);
+    }
+
+    #[test]
+    fn keyboard_reports_edges_for_one_frame() {
+        let mut input = InputHandler::<StringBindings>::new();
+        let mut keyboard = Keyboard::new();
+
+        send(&mut input, VirtualKeyCode::A, ElementState::Pressed);
+        keyboard.state_update(&input);
+        assert!(keyboard.get(VirtualKeyCode::A));
+        assert!(keyboard.get_down(VirtualKeyCode::A));
+        assert!(!keyboard.get_up(VirtualKeyCode::A));
+
+        // 押しっぱなしの間は get だけ
+        keyboard.state_update(&input);
+        assert!(keyboard.get(VirtualKeyCode::A));
+        assert!(!keyboard.get_down(VirtualKeyCode::A));
+        assert_eq!(keyboard.keys().collect::<Vec<_>>(), vec![&VirtualKeyCode::A]);
+
+        send(&mut input, VirtualKeyCode::A, ElementState::Released);
+        send(&mut input, VirtualKeyCode::B, ElementState::Pressed);
+        keyboard.state_update(&input);
+        assert!(!keyboard.get(VirtualKeyCode::A));
+        assert!(keyboard.get_up(VirtualKeyCode::A));
+        assert!(keyboard.get_down(VirtualKeyCode::B));
+
+        keyboard.state_update(&input);
+        assert!(!keyboard.get_up(VirtualKeyCode::A));
+        assert!(!keyboard.get_down(VirtualKeyCode::B));
+    }
+
+    #[test]
+    fn keyboard_system_works_with_any_bindings() {
+        let mut world = World::new();
+        let mut dispatcher = DispatcherBuilder::new()
+            .with(KeyboardSystem::<TestBindings>::new(), "keyboard_system", &[])
+            .build();
+        dispatcher.setup(&mut world.res);
+
+        send(
+            &mut world.write_resource::<InputHandler<TestBindings>>(),
+            VirtualKeyCode::Space,
+            ElementState::Pressed,
+        );
+        dispatcher.dispatch(&world.res);
+        assert!(world.read_resource::<Keyboard<TestBindings>>().get_down(VirtualKeyCode::Space));
+
+        dispatcher.dispatch(&world.res);
+        {
+            let keyboard = world.read_resource::<Keyboard<TestBindings>>();
+            assert!(keyboard.get(VirtualKeyCode::Space));
+            assert!(!keyboard.get_down(VirtualKeyCode::Space));
+        }
+
+        send(
+            &mut world.write_resource::<InputHandler<TestBindings>>(),
+            VirtualKeyCode::Space,
+            ElementState::Released,
+        );
+        dispatcher.dispatch(&world.res);
+        let keyboard = world.read_resource::<Keyboard<TestBindings>>();
+        assert!(keyboard.get_up(VirtualKeyCode::Space));
+        assert!(!keyboard.get(VirtualKeyCode::Space));
+        // StringBindings 側のリソースとは別物
+        assert!(world.res.try_fetch::<Keyboard<StringBindings>>().is_none());
+    }
+}
@@ -2,12 +2,18 @@ use amethyst::{
     prelude::*,
     core::{
         bundle::SystemBundle,
+        timing::Time,
+        shrev::{
+            EventChannel, ReaderId
+        },
         transform::Transform,
-        math::Vector3
+        math::{
+            Vector3, Vector4
+        }
     },
     renderer::{
         camera::{
-            Camera, Projection
+            ActiveCamera, Camera, Projection
         },
         sprite::{
             SpriteSheet, SpriteSheetHandle, SpriteSheetFormat,
@@ -15,20 +21,45 @@ use amethyst::{
         ImageFormat, Texture
     },
     input::{
-        InputHandler, StringBindings
+        BindingTypes, InputHandler, StringBindings
     },
     assets::{
         Loader, AssetStorage,
     },
+    window::ScreenDimensions,
     error::Error,
-    ecs::prelude::{
-        DispatcherBuilder,
-        System, Write, Read
+    ecs::{
+        prelude::{
+            DispatcherBuilder, Entity, Resources,
+            System, SystemData, Write, Read, ReadExpect, ReadStorage,
+            Join
+        },
+        storage::{
+            MaskedStorage, Storage
+        }
     },
     winit::{
-        Event, WindowEvent, ElementState, MouseButton, VirtualKeyCode
+        Event, WindowEvent, ElementState, MouseButton, MouseScrollDelta, VirtualKeyCode
     }
 };
+use std::{
+    marker::PhantomData,
+    ops::Deref
+};
+
+pub mod actions;
+pub mod camera;
+pub mod combo;
+pub mod drag;
+#[cfg(feature = "sdl_controller")]
+pub mod gamepad;
+pub mod keyboard;
+pub mod picking;
+pub mod rebind;
+pub mod replay;
+pub mod shortcut;
+pub mod text_input;
+pub mod virtual_input;
 
 pub trait TransformExt {
     fn from_xyz(x: f32, y: f32, z: f32) -> Self;
@@ -41,16 +72,64 @@ impl TransformExt for Transform {
     }
 }
 
-pub fn initialise_camera(world: &mut World, [w, h]: [f32; 2]) {
-    let (half_w, half_h) = (w * 0.5, h * 0.5);
-    let transform = Transform::from_xyz(half_w, half_h, 1.0);
-    world
-        .create_entity()
-        .with(Camera::from(Projection::orthographic(
-            -half_w, half_w, -half_h, half_h, 0.0, 0.0
-        )))
-        .with(transform)
-        .build();
+// 左下原点の正射影カメラ。細かく設定するときは camera::CameraBuilder を使う
+pub fn initialise_camera(world: &mut World, [w, h]: [f32; 2]) -> Entity {
+    camera::CameraBuilder::new(w, h).build(world)
+}
+
+// ActiveCamera が設定されていなければ最初に見つかったカメラを使う（レンダラと同じ）。
+// 揺れを描画している子のカメラ（ShakeView）は、揺れていない親のカメラに読み替える
+pub fn find_camera<'a, C, T, V>(
+    active_camera: &ActiveCamera,
+    cameras: &'a Storage<'_, Camera, C>,
+    transforms: &'a Storage<'_, Transform, T>,
+    views: &Storage<'_, camera::ShakeView, V>,
+) -> Option<(&'a Camera, &'a Transform)>
+where
+    C: Deref<Target = MaskedStorage<Camera>>,
+    T: Deref<Target = MaskedStorage<Transform>>,
+    V: Deref<Target = MaskedStorage<camera::ShakeView>>,
+{
+    if let Some(entity) = active_camera.entity {
+        let entity = views.get(entity).map_or(entity, |view| view.camera);
+        if let (Some(camera), Some(transform)) = (cameras.get(entity), transforms.get(entity)) {
+            return Some((camera, transform));
+        }
+    }
+    (cameras, transforms, !views).join().next().map(|(camera, transform, _)| (camera, transform))
+}
+
+// スクリーン座標（物理ピクセル、左上原点）をワールド座標に変換する
+pub fn screen_to_world(
+    (x, y): (f32, f32),
+    camera: &Camera,
+    transform: &Transform,
+    screen: &ScreenDimensions,
+) -> (f32, f32) {
+    let (w, h) = (screen.width(), screen.height());
+    let global = transform.global_matrix();
+    match camera.projection() {
+        Projection::Orthographic(ortho) => {
+            let view_x = ortho.left() + x / w * (ortho.right() - ortho.left());
+            let view_y = ortho.top() - y / h * (ortho.top() - ortho.bottom());
+            let world = global * Vector4::new(view_x, view_y, 0.0, 1.0);
+            (world.x, world.y)
+        }
+        Projection::Perspective(perspective) => {
+            // near と far を結ぶ直線と z = 0 平面との交点
+            let inverse = match perspective.as_matrix().try_inverse() {
+                Some(inverse) => global * inverse,
+                None => return (x, h - y),
+            };
+            let (ndc_x, ndc_y) = (2.0 * x / w - 1.0, 2.0 * y / h - 1.0);
+            let near = inverse * Vector4::new(ndc_x, ndc_y, 0.0, 1.0);
+            let far = inverse * Vector4::new(ndc_x, ndc_y, 1.0, 1.0);
+            let (near, far) = (near.xyz() / near.w, far.xyz() / far.w);
+            let t = near.z / (near.z - far.z);
+            let world = near + (far - near) * t;
+            (world.x, world.y)
+        }
+    }
 }
 
 pub fn load_sprite_sheet(
@@ -102,33 +181,133 @@ pub fn get_keyboard_input(event: &Event) -> Option<VirtualKeyCode> {
     None
 }
 
+pub fn get_received_character(event: &Event) -> Option<char> {
+    match event {
+        Event::WindowEvent { event: WindowEvent::ReceivedCharacter(c), .. } => Some(*c),
+        _ => None,
+    }
+}
+
 pub mod mouse {
     use super::*;
+    use super::camera::ShakeView;
+    use super::replay::{
+        scroll_delta, InputReplay
+    };
     use std::collections::{
         HashMap, HashSet
     };
 
-    #[derive(Default)]
-    pub struct Mouse {
+    // x, y, dx, dy はワールド座標、screen_x, screen_y はウィンドウ左上原点の物理ピクセル
+    pub struct Mouse<T: BindingTypes = StringBindings> {
         pub x: f32,
         pub y: f32,
         pub dx: f32,
         pub dy: f32,
+        pub screen_x: f32,
+        pub screen_y: f32,
+        // このフレームのホイール量（行単位とピクセル単位）
+        pub scroll_x: f32,
+        pub scroll_y: f32,
+        pub scroll_pixel_x: f32,
+        pub scroll_pixel_y: f32,
+        hidpi: f32,
 
         state: HashMap<MouseButton, bool>,
         press: HashSet<MouseButton>,
         release: HashSet<MouseButton>,
+        clicks: HashMap<MouseButton, Click>,
+        drags: HashMap<MouseButton, Drag>,
+        events: Vec<MouseEvent>,
+
+        _marker: PhantomData<T>,
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    pub struct MousePosition {
+        pub world: (f32, f32),
+        pub screen: (f32, f32),
+    }
+
+    // ボタンを押してから離すまでの移動量
+    // offset は開始位置からの合計（ワールド座標）、delta はこのフレームの移動量
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    pub struct Drag {
+        pub start: MousePosition,
+        pub offset: (f32, f32),
+        pub screen_offset: (f32, f32),
+        pub delta: (f32, f32),
+        started: bool,
+        just_started: bool,
+        ended: bool,
+    }
+
+    impl Drag {
+        fn new(start: MousePosition) -> Self {
+            Drag {
+                start,
+                offset: (0.0, 0.0),
+                screen_offset: (0.0, 0.0),
+                delta: (0.0, 0.0),
+                started: false,
+                just_started: false,
+                ended: false,
+            }
+        }
     }
 
-    impl Mouse {
+    // MouseSystem が EventChannel<MouseEvent> に流すイベント
+    #[derive(Debug, Clone, PartialEq)]
+    pub enum MouseEvent {
+        Pressed { button: MouseButton, position: MousePosition },
+        Released { button: MouseButton, position: MousePosition },
+        Moved { position: MousePosition, delta: (f32, f32) },
+        Clicked { button: MouseButton, count: u32, position: MousePosition },
+        DragStarted { button: MouseButton, position: MousePosition, start: MousePosition },
+        Dragged {
+            button: MouseButton,
+            position: MousePosition,
+            start: MousePosition,
+            delta: (f32, f32),
+        },
+        DragEnded { button: MouseButton, position: MousePosition, start: MousePosition },
+        Scrolled { position: MousePosition, lines: (f32, f32), pixels: (f32, f32) },
+    }
+
+    // 連続クリックの判定用
+    struct Click {
+        count: u32,
+        time: f64,
+        position: (f32, f32),
+    }
+
+    impl<T: BindingTypes> Default for Mouse<T> {
+        fn default() -> Self {
+            Self::new()
+        }
+    }
+
+    impl<T: BindingTypes> Mouse<T> {
         pub fn new() -> Self {
-            let mut state = HashMap::new();
-                state.insert(MouseButton::Left,   false);
-                state.insert(MouseButton::Right,  false);
-                state.insert(MouseButton::Middle, false);
             Mouse {
-                state,
-                ..Default::default()
+                x: 0.0,
+                y: 0.0,
+                dx: 0.0,
+                dy: 0.0,
+                screen_x: 0.0,
+                screen_y: 0.0,
+                scroll_x: 0.0,
+                scroll_y: 0.0,
+                scroll_pixel_x: 0.0,
+                scroll_pixel_y: 0.0,
+                hidpi: 0.0,
+                state: HashMap::new(),
+                press: HashSet::new(),
+                release: HashSet::new(),
+                clicks: HashMap::new(),
+                drags: HashMap::new(),
+                events: Vec::new(),
+                _marker: PhantomData,
             }
         }
 
@@ -147,58 +326,345 @@ pub mod mouse {
             self.release.contains(&button)
         }
 
-        fn position_update(&mut self, input: &InputHandler<StringBindings>) {
-            if let Some(mouse_pos) = input.mouse_position() {
-                let (x, y) = (mouse_pos.0 as f32, 500.0 - mouse_pos.1 as f32);
+        // これまでに押されたことのあるボタン
+        pub fn buttons(&self) -> impl Iterator<Item = &MouseButton> {
+            self.state.keys()
+        }
+
+        // 直近の押下が何回目の連続クリックか
+        pub fn click_count(&self, button: MouseButton) -> u32 {
+            self.clicks.get(&button).map_or(0, |click| click.count)
+        }
+
+        // このフレームの押下で n 回目の連続クリックになったか（n = 2 でダブルクリック）
+        pub fn get_click(&self, button: MouseButton, n: u32) -> bool {
+            self.get_down(button) && self.click_count(button) == n
+        }
+
+        // しきい値を超えて動いたドラッグのみ返す（離したフレームも含む）
+        pub fn drag(&self, button: MouseButton) -> Option<&Drag> {
+            self.drags.get(&button).filter(|drag| drag.started)
+        }
+
+        pub fn is_dragging(&self, button: MouseButton) -> bool {
+            self.drag(button).map_or(false, |drag| !drag.ended)
+        }
+
+        pub fn get_drag_start(&self, button: MouseButton) -> bool {
+            self.drag(button).map_or(false, |drag| drag.just_started)
+        }
+
+        pub fn get_drag_end(&self, button: MouseButton) -> bool {
+            self.drag(button).map_or(false, |drag| drag.ended)
+        }
+
+        pub fn screen_position(&self) -> (f32, f32) {
+            (self.screen_x, self.screen_y)
+        }
+
+        pub fn position(&self) -> MousePosition {
+            MousePosition {
+                world: (self.x, self.y),
+                screen: self.screen_position(),
+            }
+        }
+
+        // HiDPI 係数で割った論理ピクセル
+        pub fn logical_position(&self) -> (f32, f32) {
+            if self.hidpi > 0.0 {
+                (self.screen_x / self.hidpi, self.screen_y / self.hidpi)
+            } else {
+                self.screen_position()
+            }
+        }
+
+        fn position_update(
+            &mut self,
+            input: &InputHandler<T>,
+            screen: Option<&ScreenDimensions>,
+            camera: Option<(&Camera, &Transform)>,
+        ) {
+            self.dx = 0.0;
+            self.dy = 0.0;
+            self.hidpi = screen.map_or(1.0, |screen| screen.hidpi_factor() as f32);
+            if let Some(screen_pos) = input.mouse_position() {
+                let moved = screen_pos != self.screen_position();
+                self.screen_x = screen_pos.0;
+                self.screen_y = screen_pos.1;
+                // カメラがなければ左下原点のスクリーン座標をそのまま使う。
+                // ウィンドウがなければ（テストなど）変換できないので左上原点のまま
+                let (x, y) = match (camera, screen) {
+                    (Some((camera, transform)), Some(screen)) => {
+                        screen_to_world(screen_pos, camera, transform, screen)
+                    }
+                    (None, Some(screen)) => (screen_pos.0, screen.height() - screen_pos.1),
+                    (_, None) => screen_pos,
+                };
                 self.dx = x - self.x;
                 self.dy = y - self.y;
                 self.x = x;
                 self.y = y;
+                if moved {
+                    self.events.push(MouseEvent::Moved {
+                        position: self.position(),
+                        delta: (self.dx, self.dy),
+                    });
+                }
             }
         }
 
-        fn state_update(&mut self, input: &InputHandler<StringBindings>) {
+        fn scroll_update(
+            &mut self,
+            deltas: impl Iterator<Item = MouseScrollDelta>,
+            line_height: f32,
+        ) {
+            self.scroll_x = 0.0;
+            self.scroll_y = 0.0;
+            self.scroll_pixel_x = 0.0;
+            self.scroll_pixel_y = 0.0;
+            for delta in deltas {
+                // 行単位とピクセル単位の両方に換算して足し込む
+                let (lines, pixels) = match delta {
+                    MouseScrollDelta::LineDelta(x, y) => {
+                        ((x, y), (x * line_height, y * line_height))
+                    }
+                    MouseScrollDelta::PixelDelta(pos) => {
+                        let (x, y) = (pos.x as f32 * self.hidpi, pos.y as f32 * self.hidpi);
+                        ((x / line_height, y / line_height), (x, y))
+                    }
+                };
+                self.scroll_x += lines.0;
+                self.scroll_y += lines.1;
+                self.scroll_pixel_x += pixels.0;
+                self.scroll_pixel_y += pixels.1;
+            }
+            if self.scroll_pixel_x != 0.0 || self.scroll_pixel_y != 0.0 {
+                self.events.push(MouseEvent::Scrolled {
+                    position: self.position(),
+                    lines: (self.scroll_x, self.scroll_y),
+                    pixels: (self.scroll_pixel_x, self.scroll_pixel_y),
+                });
+            }
+        }
+
+        fn state_update(&mut self, input: &InputHandler<T>) {
             let down_buttons: HashSet<&MouseButton>
                 = input.mouse_buttons_that_are_down().collect();
             self.press.clear();
             self.release.clear();
-            // カスタムボタンは未実装
-            for button in &[MouseButton::Left, MouseButton::Middle, MouseButton::Right] {
-                match (self.state[button], down_buttons.contains(button)) {
+            // 初めて押されたボタン（MouseButton::Other を含む）はここで登録する
+            for button in &down_buttons {
+                self.state.entry(**button).or_insert(false);
+            }
+            let position = self.position();
+            for (button, state) in self.state.iter_mut() {
+                match (*state, down_buttons.contains(button)) {
                     (false, true) => {
                         self.press.insert(*button);
-                        *self.state.get_mut(button).unwrap() = true;
+                        self.events.push(MouseEvent::Pressed { button: *button, position });
+                        *state = true;
                     }
                     (true, false) => {
                         self.release.insert(*button);
-                        *self.state.get_mut(button).unwrap() = false;
+                        self.events.push(MouseEvent::Released { button: *button, position });
+                        *state = false;
                     }
                     _ => {}
                 }
             }
         }
+
+        fn click_update(&mut self, now: f64, interval: f32, distance: f32) {
+            let position = self.screen_position();
+            for button in &self.press {
+                let click = self.clicks.entry(*button).or_insert(Click {
+                    count: 0,
+                    time: now,
+                    position,
+                });
+                let moved = (position.0 - click.position.0).hypot(position.1 - click.position.1);
+                if click.count > 0 && now - click.time <= f64::from(interval) && moved <= distance {
+                    click.count += 1;
+                } else {
+                    click.count = 1;
+                }
+                click.time = now;
+                click.position = position;
+            }
+        }
+
+        // 押したまましきい値（物理ピクセル）を超えて動かせばドラッグ、そうでなければクリック
+        fn drag_update(&mut self, threshold: f32) {
+            let position = self.position();
+            self.drags.retain(|_, drag| !drag.ended);
+            for button in &self.press {
+                self.drags.insert(*button, Drag::new(position));
+            }
+            for (button, drag) in self.drags.iter_mut() {
+                let offset = (
+                    position.world.0 - drag.start.world.0,
+                    position.world.1 - drag.start.world.1,
+                );
+                drag.delta = (offset.0 - drag.offset.0, offset.1 - drag.offset.1);
+                drag.offset = offset;
+                drag.screen_offset = (
+                    position.screen.0 - drag.start.screen.0,
+                    position.screen.1 - drag.start.screen.1,
+                );
+                drag.just_started = false;
+                if !drag.started && drag.screen_offset.0.hypot(drag.screen_offset.1) > threshold {
+                    drag.started = true;
+                    drag.just_started = true;
+                    self.events.push(MouseEvent::DragStarted {
+                        button: *button,
+                        position,
+                        start: drag.start,
+                    });
+                } else if drag.started && drag.delta != (0.0, 0.0) {
+                    self.events.push(MouseEvent::Dragged {
+                        button: *button,
+                        position,
+                        start: drag.start,
+                        delta: drag.delta,
+                    });
+                }
+                if self.release.contains(button) {
+                    drag.ended = true;
+                    if drag.started {
+                        self.events.push(MouseEvent::DragEnded {
+                            button: *button,
+                            position,
+                            start: drag.start,
+                        });
+                    } else {
+                        self.events.push(MouseEvent::Clicked {
+                            button: *button,
+                            count: self.clicks.get(button).map_or(1, |click| click.count),
+                            position,
+                        });
+                    }
+                }
+            }
+        }
+    }
+
+    pub struct MouseSystem<T: BindingTypes = StringBindings> {
+        reader: Option<ReaderId<Event>>,
+        scroll_line_height: f32,
+        click_interval: f32,
+        click_distance: f32,
+        drag_threshold: f32,
+        _marker: PhantomData<T>,
     }
 
-    pub struct MouseSystem;
+    impl<T: BindingTypes> MouseSystem<T> {
+        pub fn new() -> Self {
+            MouseSystem {
+                reader: None,
+                scroll_line_height: 20.0,
+                click_interval: 0.5,
+                click_distance: 4.0,
+                drag_threshold: 4.0,
+                _marker: PhantomData,
+            }
+        }
+
+        pub fn with_scroll_line_height(mut self, line_height: f32) -> Self {
+            self.scroll_line_height = line_height;
+            self
+        }
 
-    impl<'s> System<'s> for MouseSystem {
+        pub fn with_click(mut self, interval: f32, distance: f32) -> Self {
+            self.click_interval = interval;
+            self.click_distance = distance;
+            self
+        }
+
+        pub fn with_drag_threshold(mut self, threshold: f32) -> Self {
+            self.drag_threshold = threshold;
+            self
+        }
+    }
+
+    impl<T: BindingTypes> Default for MouseSystem<T> {
+        fn default() -> Self {
+            Self::new()
+        }
+    }
+
+    impl<'s, T: BindingTypes> System<'s> for MouseSystem<T> {
         type SystemData = (
-            Write<'s, Mouse>,
-            Read<'s, InputHandler<StringBindings>>
+            Write<'s, Mouse<T>>,
+            Read<'s, InputHandler<T>>,
+            Read<'s, EventChannel<Event>>,
+            Read<'s, Time>,
+            Option<Read<'s, ScreenDimensions>>,
+            Read<'s, ActiveCamera>,
+            ReadStorage<'s, Camera>,
+            ReadStorage<'s, Transform>,
+            ReadStorage<'s, ShakeView>,
+            Write<'s, EventChannel<MouseEvent>>,
+            Read<'s, InputReplay>,
         );
 
-        fn run(&mut self, (mut mouse, input): Self::SystemData) {
-            mouse.position_update(&input);
+        fn run(
+            &mut self,
+            (
+                mut mouse, input, events, time,
+                screen, active_camera, cameras, transforms, views, mut mouse_events, replay
+            ): Self::SystemData
+        ) {
+            let camera = find_camera(&active_camera, &cameras, &transforms, &views);
+            mouse.position_update(&input, screen.as_deref(), camera);
+            let live = events
+                .read(self.reader.as_mut().expect("MouseSystem::setup was not called"))
+                .filter_map(scroll_delta);
+            // 再生中は実際のホイール入力を無視する
+            match replay.playing_frame() {
+                Some(frame) => {
+                    mouse.scroll_update(frame.scroll.iter().cloned(), self.scroll_line_height)
+                }
+                None => mouse.scroll_update(live, self.scroll_line_height),
+            }
             mouse.state_update(&input);
+            mouse.click_update(
+                replay.absolute_seconds(&time),
+                self.click_interval,
+                self.click_distance,
+            );
+            mouse.drag_update(self.drag_threshold);
+            mouse_events.drain_vec_write(&mut mouse.events);
+        }
+
+        fn setup(&mut self, res: &mut Resources) {
+            Self::SystemData::setup(res);
+            self.reader = Some(res.fetch_mut::<EventChannel<Event>>().register_reader());
         }
     }
 
-    #[derive(Default)]
-    pub struct MouseBundle<'a> {
-        dep: &'a [&'a str]
+    pub struct MouseBundle<'a, T: BindingTypes = StringBindings> {
+        dep: &'a [&'a str],
+        scroll_line_height: f32,
+        click_interval: f32,
+        click_distance: f32,
+        drag_threshold: f32,
+        _marker: PhantomData<T>,
     }
 
-    impl<'a> MouseBundle<'a> {
+    impl<'a, T: BindingTypes> Default for MouseBundle<'a, T> {
+        fn default() -> Self {
+            MouseBundle {
+                dep: &[],
+                scroll_line_height: 20.0,
+                click_interval: 0.5,
+                click_distance: 4.0,
+                drag_threshold: 4.0,
+                _marker: PhantomData,
+            }
+        }
+    }
+
+    impl<'a, T: BindingTypes> MouseBundle<'a, T> {
         pub fn new() -> Self {
             Default::default()
         }
@@ -207,9 +673,28 @@ pub mod mouse {
             self.dep = dep;
             self
         }
+
+        // 1 行分のスクロールを何ピクセルとみなすか
+        pub fn with_scroll_line_height(mut self, line_height: f32) -> Self {
+            self.scroll_line_height = line_height;
+            self
+        }
+
+        // 連続クリックとみなす最大間隔（秒）と最大移動距離（物理ピクセル）
+        pub fn with_click(mut self, interval: f32, distance: f32) -> Self {
+            self.click_interval = interval;
+            self.click_distance = distance;
+            self
+        }
+
+        // ドラッグ開始とみなす移動距離（物理ピクセル）
+        pub fn with_drag_threshold(mut self, threshold: f32) -> Self {
+            self.drag_threshold = threshold;
+            self
+        }
     }
 
-    impl<'a, 'b, 'c> SystemBundle<'a, 'b> for MouseBundle<'c> {
+    impl<'a, 'b, 'c, T: BindingTypes> SystemBundle<'a, 'b> for MouseBundle<'c, T> {
         fn build(self, builder: &mut DispatcherBuilder<'a, 'b>) -> Result<(), Error> {
             let dep = {
                 let mut vec = self.dep.to_vec();
@@ -217,7 +702,10 @@ pub mod mouse {
                 vec
             };
             builder.add(
-                MouseSystem,
+                MouseSystem::<T>::new()
+                    .with_scroll_line_height(self.scroll_line_height)
+                    .with_click(self.click_interval, self.click_distance)
+                    .with_drag_threshold(self.drag_threshold),
                 "mouse_system",
                 &dep,
             );
@@ -226,6 +714,6 @@ pub mod mouse {
     }
 
     pub fn initialise_mouse(world: &mut World) {
-        world.add_resource(Mouse::new());
+        world.add_resource(Mouse::<StringBindings>::new());
     }
 }
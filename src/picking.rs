@@ -0,0 +1,404 @@
+use super::*;
+use super::drag::{
+    global_z, world_to_local, HitShape
+};
+use super::mouse::Mouse;
+use amethyst::{
+    core::{
+        Hidden, HiddenPropagate
+    },
+    ecs::prelude::{
+        Component, Entities, Entity, NullStorage, WriteStorage
+    },
+    renderer::SpriteRender,
+};
+
+// この印が付いたスプライトだけが拾われる
+#[derive(Default)]
+pub struct Pickable;
+
+impl Component for Pickable {
+    type Storage = NullStorage<Self>;
+}
+
+// カーソルの下にある一番手前のエンティティに付く
+#[derive(Default)]
+pub struct Hovered;
+
+impl Component for Hovered {
+    type Storage = NullStorage<Self>;
+}
+
+// エンティティの上でボタンを押し、まだ離していない間付く
+#[derive(Default)]
+pub struct Pressed;
+
+impl Component for Pressed {
+    type Storage = NullStorage<Self>;
+}
+
+#[derive(Default)]
+pub struct Picking {
+    pub hovered: Option<Entity>,
+}
+
+// 一番手前のエンティティが変わったら Exit と Enter、変わらなければ毎フレーム Stay
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PointerEvent {
+    Enter(Entity),
+    Exit(Entity),
+    Stay(Entity),
+}
+
+// スプライトの大きさとオフセット、Transform のグローバル行列で判定する
+pub fn sprite_contains(
+    sprite_sheets: &AssetStorage<SpriteSheet>,
+    sprite_render: &SpriteRender,
+    transform: &Transform,
+    point: (f32, f32),
+) -> bool {
+    let sprite = match sprite_sheets
+        .get(&sprite_render.sprite_sheet)
+        .and_then(|sheet| sheet.sprites.get(sprite_render.sprite_number))
+    {
+        Some(sprite) => sprite,
+        None => return false,
+    };
+    // オフセットの分だけずれた位置に描かれる
+    match world_to_local(transform, point) {
+        Some((x, y)) => HitShape::Rect(sprite.width, sprite.height)
+            .contains((x + sprite.offsets[0], y + sprite.offsets[1])),
+        None => false,
+    }
+}
+
+pub struct PickingSystem<T: BindingTypes = StringBindings> {
+    _marker: PhantomData<T>,
+}
+
+impl<T: BindingTypes> PickingSystem<T> {
+    pub fn new() -> Self {
+        PickingSystem {
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<T: BindingTypes> Default for PickingSystem<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<'s, T: BindingTypes> System<'s> for PickingSystem<T> {
+    type SystemData = (
+        Entities<'s>,
+        Read<'s, Mouse<T>>,
+        Read<'s, AssetStorage<SpriteSheet>>,
+        ReadStorage<'s, SpriteRender>,
+        ReadStorage<'s, Transform>,
+        ReadStorage<'s, Pickable>,
+        ReadStorage<'s, Hidden>,
+        ReadStorage<'s, HiddenPropagate>,
+        WriteStorage<'s, Hovered>,
+        WriteStorage<'s, Pressed>,
+        Write<'s, Picking>,
+        Write<'s, EventChannel<PointerEvent>>,
+    );
+
+    fn run(
+        &mut self,
+        (
+            entities, mouse, sprite_sheets, sprite_renders, transforms, pickables,
+            hiddens, hidden_propagates, mut hovereds, mut presseds, mut picking,
+            mut pointer_events
+        ): Self::SystemData
+    ) {
+        let cursor = (mouse.x, mouse.y);
+
+        // z が大きいほど手前
+        let mut top: Option<(Entity, f32)> = None;
+        for (entity, sprite_render, transform, _, _, _) in (
+            &entities, &sprite_renders, &transforms, &pickables,
+            !&hiddens, !&hidden_propagates
+        ).join() {
+            let z = global_z(transform);
+            if top.map_or(true, |(_, top_z)| z > top_z)
+                && sprite_contains(&sprite_sheets, sprite_render, transform, cursor)
+            {
+                top = Some((entity, z));
+            }
+        }
+        let hovered = top.map(|(entity, _)| entity);
+        if hovered == picking.hovered {
+            if let Some(entity) = hovered {
+                pointer_events.single_write(PointerEvent::Stay(entity));
+            }
+        } else {
+            if let Some(entity) = picking.hovered.filter(|entity| entities.is_alive(*entity)) {
+                pointer_events.single_write(PointerEvent::Exit(entity));
+            }
+            if let Some(entity) = hovered {
+                pointer_events.single_write(PointerEvent::Enter(entity));
+            }
+        }
+        picking.hovered = hovered;
+
+        hovereds.clear();
+        if let Some(entity) = picking.hovered {
+            hovereds.insert(entity, Hovered).expect("entity is alive");
+        }
+
+        // 押したときのエンティティをすべてのボタンが離されるまで保持する
+        let pressed_any = mouse.buttons().any(|button| mouse.get_down(*button));
+        let held_any = mouse.buttons().any(|button| mouse.get(*button));
+        if !held_any {
+            presseds.clear();
+        } else if pressed_any {
+            if let Some(entity) = picking.hovered {
+                presseds.insert(entity, Pressed).expect("entity is alive");
+            }
+        }
+    }
+}
+
+pub struct PickingBundle<'a, T: BindingTypes = StringBindings> {
+    dep: &'a [&'a str],
+    _marker: PhantomData<T>,
+}
+
+impl<'a, T: BindingTypes> Default for PickingBundle<'a, T> {
+    fn default() -> Self {
+        PickingBundle {
+            dep: &[],
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<'a, T: BindingTypes> PickingBundle<'a, T> {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    pub fn with_dep(mut self, dep: &'a [&'a str]) -> Self {
+        self.dep = dep;
+        self
+    }
+}
+
+// MouseBundle の後に追加すること
+impl<'a, 'b, 'c, T: BindingTypes> SystemBundle<'a, 'b> for PickingBundle<'c, T> {
+    fn build(self, builder: &mut DispatcherBuilder<'a, 'b>) -> Result<(), Error> {
+        let dep = {
+            let mut vec = self.dep.to_vec();
+            vec.push("mouse_system");
+            vec
+        };
+        builder.add(
+            PickingSystem::<T>::new(),
+            "picking_system",
+            &dep,
+        );
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::mouse::MouseSystem;
+    use amethyst::{
+        core::{
+            Parent, TransformBundle
+        },
+        ecs::prelude::Dispatcher,
+        input::InputSystem,
+        renderer::sprite::Sprite,
+        winit::{
+            dpi::LogicalPosition,
+            DeviceId, ModifiersState, WindowId
+        },
+    };
+    use std::sync::Arc;
+
+    const HEIGHT: f32 = 200.0;
+
+    fn setup() -> (World, Dispatcher<'static, 'static>) {
+        let mut world = World::new();
+        let mut builder = DispatcherBuilder::new()
+            .with(InputSystem::<StringBindings>::new(None), "input_system", &[])
+            .with(MouseSystem::<StringBindings>::new(), "mouse_system", &["input_system"])
+            .with(PickingSystem::<StringBindings>::new(), "picking_system", &["mouse_system"]);
+        TransformBundle::new()
+            .with_dep(&["picking_system"])
+            .build(&mut builder)
+            .unwrap();
+        let mut dispatcher = builder.build();
+        dispatcher.setup(&mut world.res);
+        world.add_resource(ScreenDimensions::new(200, HEIGHT as u32, 1.0));
+        (world, dispatcher)
+    }
+
+    // テクスチャは読み込まれないが、判定には大きさとオフセットしか使わない
+    fn sprite_sheet(world: &mut World, sprites: Vec<Sprite>) -> SpriteSheetHandle {
+        let pool = rayon::ThreadPoolBuilder::new().num_threads(1).build().unwrap();
+        let loader = Loader::new(".", Arc::new(pool));
+        let texture = loader.load(
+            "missing.png",
+            ImageFormat::default(),
+            (),
+            &AssetStorage::<Texture>::new(),
+        );
+        world
+            .write_resource::<AssetStorage<SpriteSheet>>()
+            .insert(SpriteSheet { texture, sprites })
+    }
+
+    fn spawn(world: &mut World, sheet: &SpriteSheetHandle, (x, y, z): (f32, f32, f32)) -> Entity {
+        world
+            .create_entity()
+            .with(SpriteRender { sprite_sheet: sheet.clone(), sprite_number: 0 })
+            .with(Transform::from_xyz(x, y, z))
+            .with(Pickable)
+            .build()
+    }
+
+    fn send(world: &mut World, event: WindowEvent) {
+        world.write_resource::<EventChannel<Event>>().single_write(Event::WindowEvent {
+            window_id: unsafe { WindowId::dummy() },
+            event,
+        });
+    }
+
+    // カメラがないので左下原点のワールド座標で指定する
+    fn move_to(world: &mut World, x: f32, y: f32) {
+        send(world, WindowEvent::CursorMoved {
+            device_id: unsafe { DeviceId::dummy() },
+            position: LogicalPosition::new(f64::from(x), f64::from(HEIGHT - y)),
+            modifiers: ModifiersState::default(),
+        });
+    }
+
+    fn left(world: &mut World, state: ElementState) {
+        send(world, WindowEvent::MouseInput {
+            device_id: unsafe { DeviceId::dummy() },
+            state,
+            button: MouseButton::Left,
+            modifiers: ModifiersState::default(),
+        });
+    }
+
+    fn frame(world: &mut World, dispatcher: &mut Dispatcher) {
+        dispatcher.dispatch(&world.res);
+        world.maintain();
+    }
+
+    fn hovered(world: &World) -> Vec<Entity> {
+        (&world.entities(), &world.read_storage::<Hovered>()).join().map(|(e, _)| e).collect()
+    }
+
+    fn pressed(world: &World) -> Vec<Entity> {
+        (&world.entities(), &world.read_storage::<Pressed>()).join().map(|(e, _)| e).collect()
+    }
+
+    #[test]
+    fn sprite_contains_applies_offsets_and_transform() {
+        let (mut world, mut dispatcher) = setup();
+        // 中心から左に 5px ずれて描かれる 20x10 のスプライト
+        let sheet = sprite_sheet(&mut world, vec![Sprite::from(((20.0, 10.0), [5.0, 0.0], [0.0; 4]))]);
+        let plain = spawn(&mut world, &sheet, (100.0, 100.0, 0.0));
+        let mut scaled = Transform::from_xyz(0.0, 50.0, 0.0);
+        scaled.set_scale(Vector3::new(2.0, 2.0, 1.0));
+        let parent = world.create_entity().with(scaled).build();
+        let child = world
+            .create_entity()
+            .with(SpriteRender { sprite_sheet: sheet.clone(), sprite_number: 0 })
+            .with(Transform::from_xyz(20.0, 0.0, 0.0))
+            .with(Parent { entity: parent })
+            .build();
+        frame(&mut world, &mut dispatcher);
+
+        let sheets = world.read_resource::<AssetStorage<SpriteSheet>>();
+        let renders = world.read_storage::<SpriteRender>();
+        let transforms = world.read_storage::<Transform>();
+        let contains = |entity, point| {
+            sprite_contains(&sheets, renders.get(entity).unwrap(), transforms.get(entity).unwrap(), point)
+        };
+        assert!(contains(plain, (86.0, 100.0)));
+        assert!(contains(plain, (104.0, 104.0)));
+        assert!(!contains(plain, (106.0, 100.0)));
+        assert!(!contains(plain, (84.0, 100.0)));
+        assert!(!contains(plain, (100.0, 106.0)));
+        // 親の拡大でスプライトは 40x20 になり、中心は (30, 50)
+        assert!(contains(child, (11.0, 59.0)));
+        assert!(contains(child, (49.0, 41.0)));
+        assert!(!contains(child, (51.0, 50.0)));
+        assert!(!contains(child, (30.0, 61.0)));
+    }
+
+    #[test]
+    fn picking_hovers_the_topmost_sprite_and_tracks_pressed() {
+        let (mut world, mut dispatcher) = setup();
+        let sheet = sprite_sheet(&mut world, vec![Sprite::from(((40.0, 40.0), [0.0, 0.0], [0.0; 4]))]);
+        let back = spawn(&mut world, &sheet, (100.0, 100.0, 0.0));
+        let front = spawn(&mut world, &sheet, (120.0, 100.0, 0.5));
+        frame(&mut world, &mut dispatcher);
+
+        move_to(&mut world, 110.0, 100.0);
+        frame(&mut world, &mut dispatcher);
+        assert_eq!(world.read_resource::<Picking>().hovered, Some(front));
+        assert_eq!(hovered(&world), vec![front]);
+
+        // 手前のものが隠れていれば後ろのものを拾う
+        world.write_storage::<Hidden>().insert(front, Hidden).unwrap();
+        frame(&mut world, &mut dispatcher);
+        assert_eq!(hovered(&world), vec![back]);
+        world.write_storage::<Hidden>().remove(front);
+
+        move_to(&mut world, 130.0, 100.0);
+        left(&mut world, ElementState::Pressed);
+        frame(&mut world, &mut dispatcher);
+        assert_eq!(pressed(&world), vec![front]);
+
+        // 押したまま外に出ても Pressed は残り、Hovered は外れる
+        move_to(&mut world, 190.0, 190.0);
+        frame(&mut world, &mut dispatcher);
+        assert_eq!(pressed(&world), vec![front]);
+        assert!(hovered(&world).is_empty());
+        assert_eq!(world.read_resource::<Picking>().hovered, None);
+
+        left(&mut world, ElementState::Released);
+        frame(&mut world, &mut dispatcher);
+        assert!(pressed(&world).is_empty());
+    }
+
+    #[test]
+    fn pointer_events_enter_stay_and_exit() {
+        let (mut world, mut dispatcher) = setup();
+        let mut reader = world.write_resource::<EventChannel<PointerEvent>>().register_reader();
+        let sheet = sprite_sheet(&mut world, vec![Sprite::from(((20.0, 20.0), [0.0, 0.0], [0.0; 4]))]);
+        let a = spawn(&mut world, &sheet, (50.0, 50.0, 0.0));
+        let b = spawn(&mut world, &sheet, (100.0, 50.0, 0.0));
+        move_to(&mut world, 150.0, 150.0);
+        frame(&mut world, &mut dispatcher);
+
+        let mut step = |world: &mut World, (x, y): (f32, f32)| {
+            move_to(world, x, y);
+            frame(world, &mut dispatcher);
+            let events = world.read_resource::<EventChannel<PointerEvent>>();
+            events.read(&mut reader).cloned().collect::<Vec<_>>()
+        };
+        assert_eq!(step(&mut world, (150.0, 150.0)), vec![]);
+        assert_eq!(step(&mut world, (50.0, 50.0)), vec![PointerEvent::Enter(a)]);
+        assert_eq!(step(&mut world, (52.0, 50.0)), vec![PointerEvent::Stay(a)]);
+        assert_eq!(step(&mut world, (100.0, 50.0)), vec![PointerEvent::Exit(a), PointerEvent::Enter(b)]);
+        assert_eq!(step(&mut world, (150.0, 150.0)), vec![PointerEvent::Exit(b)]);
+        assert_eq!(step(&mut world, (150.0, 150.0)), vec![]);
+
+        // 消されたエンティティの Exit は出さない
+        assert_eq!(step(&mut world, (50.0, 50.0)), vec![PointerEvent::Enter(a)]);
+        world.delete_entity(a).unwrap();
+        assert_eq!(step(&mut world, (50.0, 50.0)), vec![]);
+    }
+}
@@ -0,0 +1,715 @@
+use super::*;
+#[cfg(feature = "sdl_controller")]
+use super::gamepad::ControllerIds;
+use super::replay::InputReplay;
+use amethyst::input::{
+    Axis, BindingError, Bindings, Button, ControllerAxis, InputEvent
+};
+use serde::{
+    de::DeserializeOwned, Serialize
+};
+use std::{
+    fs::File,
+    io::BufReader,
+    path::{
+        Path, PathBuf
+    }
+};
+
+// 割り当て先
+#[derive(Debug)]
+pub enum RebindTarget<T: BindingTypes> {
+    // 既存の割り当てをすべて置き換える
+    Action(T::Action),
+    // エミュレート軸（pos / neg の 2 ボタン）の片側
+    AxisPositive(T::Axis),
+    AxisNegative(T::Axis),
+    // コントローラのアナログ軸
+    ControllerAxis(T::Axis),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Captured {
+    Button(Button),
+    ControllerAxis { controller_id: u32, axis: ControllerAxis },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConflictPolicy {
+    // 競合したら割り当てない
+    Reject,
+    // 競合したアクションから外して割り当てる（軸との競合は Reject と同じ）
+    Steal,
+}
+
+#[derive(Debug)]
+pub enum RebindEvent<T: BindingTypes> {
+    Bound { target: RebindTarget<T>, captured: Captured },
+    Conflict { target: RebindTarget<T>, captured: Captured, conflicts: Vec<RebindTarget<T>> },
+    Failed { target: RebindTarget<T>, captured: Captured, error: String },
+    Cancelled(RebindTarget<T>),
+    SaveFailed(String),
+}
+
+pub struct Rebinding<T: BindingTypes = StringBindings> {
+    pending: Option<RebindTarget<T>>,
+    policy: ConflictPolicy,
+    cancel_key: Option<VirtualKeyCode>,
+    axis_threshold: f32,
+    save_path: Option<PathBuf>,
+    events: Vec<RebindEvent<T>>,
+}
+
+impl<T: BindingTypes> Default for Rebinding<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T: BindingTypes> Rebinding<T> {
+    pub fn new() -> Self {
+        Rebinding {
+            pending: None,
+            policy: ConflictPolicy::Reject,
+            cancel_key: Some(VirtualKeyCode::Escape),
+            axis_threshold: 0.5,
+            save_path: None,
+            events: Vec::new(),
+        }
+    }
+
+    pub fn with_policy(mut self, policy: ConflictPolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    pub fn with_cancel_key(mut self, key: Option<VirtualKeyCode>) -> Self {
+        self.cancel_key = key;
+        self
+    }
+
+    // コントローラの軸をどこまで倒したら取り込むか
+    pub fn with_axis_threshold(mut self, threshold: f32) -> Self {
+        self.axis_threshold = threshold;
+        self
+    }
+
+    // 割り当てが成功するたびにここへ保存する
+    pub fn with_save_path(mut self, path: impl Into<PathBuf>) -> Self {
+        self.save_path = Some(path.into());
+        self
+    }
+
+    // 次に押されたキー・ボタン（ControllerAxis なら倒された軸）を target に割り当てる
+    pub fn capture(&mut self, target: RebindTarget<T>) {
+        self.cancel();
+        self.pending = Some(target);
+    }
+
+    pub fn cancel(&mut self) {
+        if let Some(target) = self.pending.take() {
+            self.events.push(RebindEvent::Cancelled(target));
+        }
+    }
+
+    pub fn is_capturing(&self) -> bool {
+        self.pending.is_some()
+    }
+
+    pub fn capturing(&self) -> Option<&RebindTarget<T>> {
+        self.pending.as_ref()
+    }
+
+    fn capture_event(&self, event: &InputEvent<T>, ids: &mut ControllerIdsData) -> Option<Captured> {
+        let target = self.pending.as_ref()?;
+        match (target, event) {
+            (RebindTarget::ControllerAxis(_), InputEvent::ControllerAxisMoved { which, axis, value })
+                if value.abs() >= self.axis_threshold =>
+            {
+                // バインドには SDL の番号ではなく InputHandler の id を書く
+                controller_id(ids, *which)
+                    .map(|controller_id| Captured::ControllerAxis { controller_id, axis: *axis })
+            }
+            (RebindTarget::ControllerAxis(_), _) => None,
+            // 位置ではなく文字で割り当てる
+            (_, InputEvent::ButtonPressed(Button::ScanCode(_))) => None,
+            (RebindTarget::Action(_), InputEvent::ButtonPressed(button)) => {
+                Some(Captured::Button(*button))
+            }
+            // ホイールはエミュレート軸に使えない
+            (_, InputEvent::ButtonPressed(Button::MouseWheel(_))) => None,
+            (_, InputEvent::ButtonPressed(button)) => Some(Captured::Button(*button)),
+            _ => None,
+        }
+    }
+}
+
+// SDL の番号から InputHandler の id への対応表（gamepad::ControllerIds）。
+// sdl_controller なしではコントローラのイベントが届かないので、対応表も持たない
+#[cfg(feature = "sdl_controller")]
+type ControllerIdsData<'s> = Write<'s, ControllerIds>;
+#[cfg(not(feature = "sdl_controller"))]
+type ControllerIdsData<'s> = ();
+
+#[cfg(feature = "sdl_controller")]
+fn sync_ids<T: BindingTypes>(ids: &mut ControllerIdsData, input: &InputHandler<T>) {
+    ids.sync(input);
+}
+
+#[cfg(not(feature = "sdl_controller"))]
+fn sync_ids<T: BindingTypes>(_ids: &mut ControllerIdsData, _input: &InputHandler<T>) {}
+
+#[cfg(feature = "sdl_controller")]
+fn observe_ids<T: BindingTypes>(ids: &mut ControllerIdsData, event: &InputEvent<T>) {
+    ids.observe(event);
+}
+
+#[cfg(not(feature = "sdl_controller"))]
+fn observe_ids<T: BindingTypes>(_ids: &mut ControllerIdsData, _event: &InputEvent<T>) {}
+
+#[cfg(feature = "sdl_controller")]
+fn controller_id(ids: &mut ControllerIdsData, which: u32) -> Option<u32> {
+    ids.id(which)
+}
+
+#[cfg(not(feature = "sdl_controller"))]
+fn controller_id(_ids: &mut ControllerIdsData, _which: u32) -> Option<u32> {
+    None
+}
+
+fn is_cancel<T: BindingTypes>(event: &InputEvent<T>, cancel_key: Option<VirtualKeyCode>) -> bool {
+    match (event, cancel_key) {
+        (InputEvent::ButtonPressed(Button::Key(key)), Some(cancel)) => *key == cancel,
+        _ => false,
+    }
+}
+
+// captured を target 以外で使っている割り当て
+fn conflicts<T: BindingTypes>(
+    bindings: &Bindings<T>,
+    target: &RebindTarget<T>,
+    captured: Captured,
+) -> Vec<RebindTarget<T>> {
+    let mut conflicts = Vec::new();
+    match captured {
+        Captured::Button(button) => {
+            for action in bindings.actions() {
+                if let RebindTarget::Action(ref id) = *target {
+                    if id == action {
+                        continue;
+                    }
+                }
+                if bindings.action_bindings(action).any(|combo| combo == [button]) {
+                    conflicts.push(RebindTarget::Action(action.clone()));
+                }
+            }
+            for id in bindings.axes() {
+                if let Some(Axis::Emulated { pos, neg }) = bindings.axis(id) {
+                    let (skip_pos, skip_neg) = match *target {
+                        RebindTarget::AxisPositive(ref axis) => (axis == id, false),
+                        RebindTarget::AxisNegative(ref axis) => (false, axis == id),
+                        _ => (false, false),
+                    };
+                    if !skip_pos && *pos == button {
+                        conflicts.push(RebindTarget::AxisPositive(id.clone()));
+                    }
+                    if !skip_neg && *neg == button {
+                        conflicts.push(RebindTarget::AxisNegative(id.clone()));
+                    }
+                }
+            }
+        }
+        Captured::ControllerAxis { controller_id, axis } => {
+            for id in bindings.axes() {
+                if let RebindTarget::ControllerAxis(ref target_id) = *target {
+                    if target_id == id {
+                        continue;
+                    }
+                }
+                if let Some(Axis::Controller { controller_id: c, axis: a, .. }) = bindings.axis(id) {
+                    if *c == controller_id && *a == axis {
+                        conflicts.push(RebindTarget::ControllerAxis(id.clone()));
+                    }
+                }
+            }
+        }
+    }
+    conflicts
+}
+
+fn remove_action<T: BindingTypes>(bindings: &mut Bindings<T>, action: &T::Action) -> Result<(), String> {
+    let combos: Vec<Vec<Button>> = bindings.action_bindings(action).map(|c| c.to_vec()).collect();
+    for combo in combos {
+        bindings
+            .remove_action_binding(action, &combo)
+            .map_err(|e| format!("{:?}: {}", action, e))?;
+    }
+    Ok(())
+}
+
+// Steal: 競合したアクションから captured のボタンを外す
+fn steal<T: BindingTypes>(
+    bindings: &mut Bindings<T>,
+    conflicts: &[RebindTarget<T>],
+    button: Button,
+) -> Result<(), String> {
+    for conflict in conflicts {
+        if let RebindTarget::Action(action) = conflict {
+            bindings
+                .remove_action_binding(action, &[button])
+                .map_err(|e| format!("{:?}: {}", action, e))?;
+        }
+    }
+    Ok(())
+}
+
+fn apply<T: BindingTypes>(
+    bindings: &mut Bindings<T>,
+    target: &RebindTarget<T>,
+    captured: Captured,
+) -> Result<(), String> {
+    match (target, captured) {
+        (RebindTarget::Action(action), Captured::Button(button)) => {
+            // 外したあとで入れられないと分かっても困るので、先に競合を確かめる
+            if let Some(conflict) = conflicts(bindings, target, captured).first() {
+                return Err(format!("{:?} is already bound to {:?}", captured, conflict));
+            }
+            remove_action(bindings, action)?;
+            bindings
+                .insert_action_binding(action.clone(), Some(button))
+                .map_err(|e| format!("{:?}", e))
+        }
+        (RebindTarget::AxisPositive(id), Captured::Button(button))
+        | (RebindTarget::AxisNegative(id), Captured::Button(button)) => {
+            let axis = match bindings.axis(id) {
+                Some(Axis::Emulated { pos, neg }) => match target {
+                    RebindTarget::AxisPositive(_) => Axis::Emulated { pos: button, neg: *neg },
+                    _ => Axis::Emulated { pos: *pos, neg: button },
+                },
+                _ => return Err(format!("{:?} is not an emulated axis", id)),
+            };
+            bindings.insert_axis(id.clone(), axis).map(|_| ()).map_err(|e| format!("{:?}", e))
+        }
+        (RebindTarget::ControllerAxis(id), Captured::ControllerAxis { controller_id, axis }) => {
+            // 反転とデッドゾーンの設定は引き継ぐ
+            let (invert, dead_zone) = match bindings.axis(id) {
+                Some(Axis::Controller { invert, dead_zone, .. }) => (*invert, *dead_zone),
+                _ => (false, 0.1),
+            };
+            let axis = Axis::Controller { controller_id, axis, invert, dead_zone };
+            bindings.insert_axis(id.clone(), axis).map(|_| ()).map_err(|e| format!("{:?}", e))
+        }
+        _ => Err(format!("{:?} cannot be bound to {:?}", captured, target)),
+    }
+}
+
+pub fn save_bindings<T: BindingTypes>(
+    bindings: &Bindings<T>,
+    path: impl AsRef<Path>,
+) -> Result<(), Error>
+where
+    Bindings<T>: Serialize,
+{
+    let text = ron::ser::to_string_pretty(bindings, Default::default()).map_err(Error::new)?;
+    std::fs::write(path, text).map_err(Error::new)
+}
+
+// 同梱の既定値にユーザーのファイルを重ねる。
+// ユーザーのファイルにないアクション・軸だけ既定値から足す（ユーザーの割り当てと競合するものは足さない）
+pub fn load_bindings<T: BindingTypes>(
+    defaults: impl AsRef<Path>,
+    user: impl AsRef<Path>,
+) -> Result<Bindings<T>, Error>
+where
+    Bindings<T>: DeserializeOwned,
+{
+    let mut defaults: Bindings<T> = load_ron(defaults)?;
+    if !user.as_ref().exists() {
+        defaults.check_invariants().map_err(|e| Error::from_string(format!("{:?}", e)))?;
+        return Ok(defaults);
+    }
+    let mut bindings: Bindings<T> = load_ron(user)?;
+    bindings.check_invariants().map_err(|e| Error::from_string(format!("{:?}", e)))?;
+
+    let actions: Vec<T::Action> = defaults
+        .actions()
+        .filter(|action| bindings.action_bindings(*action).next().is_none())
+        .cloned()
+        .collect();
+    for action in actions {
+        let combos: Vec<Vec<Button>> =
+            defaults.action_bindings(&action).map(|c| c.to_vec()).collect();
+        for combo in combos {
+            match bindings.insert_action_binding(action.clone(), combo) {
+                Ok(())
+                | Err(BindingError::ComboAlreadyBound(_))
+                | Err(BindingError::ButtonBoundToAxis(..)) => {}
+                Err(e) => return Err(Error::from_string(format!("{:?}", e))),
+            }
+        }
+    }
+    let axes: Vec<T::Axis> = defaults
+        .axes()
+        .filter(|id| bindings.axis(*id).is_none())
+        .cloned()
+        .collect();
+    for id in axes {
+        if let Some(axis) = defaults.remove_axis(&id) {
+            match bindings.insert_axis(id, axis) {
+                Ok(_)
+                | Err(BindingError::AxisButtonAlreadyBoundToAxis(..))
+                | Err(BindingError::AxisButtonAlreadyBoundToAction(..))
+                | Err(BindingError::ControllerAxisAlreadyBound(_))
+                | Err(BindingError::MouseWheelAxisAlreadyBound(_)) => {}
+                Err(e) => return Err(Error::from_string(format!("{:?}", e))),
+            }
+        }
+    }
+    Ok(bindings)
+}
+
+fn load_ron<D: DeserializeOwned>(path: impl AsRef<Path>) -> Result<D, Error> {
+    let file = File::open(path).map_err(Error::new)?;
+    ron::de::from_reader(BufReader::new(file)).map_err(Error::new)
+}
+
+pub struct RebindSystem<T: BindingTypes = StringBindings>
+where
+    Bindings<T>: Serialize,
+{
+    reader: Option<ReaderId<InputEvent<T>>>,
+}
+
+impl<T: BindingTypes> RebindSystem<T>
+where
+    Bindings<T>: Serialize,
+{
+    pub fn new() -> Self {
+        RebindSystem {
+            reader: None,
+        }
+    }
+}
+
+impl<T: BindingTypes> Default for RebindSystem<T>
+where
+    Bindings<T>: Serialize,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<'s, T: BindingTypes> System<'s> for RebindSystem<T>
+where
+    Bindings<T>: Serialize,
+{
+    type SystemData = (
+        Write<'s, Rebinding<T>>,
+        ControllerIdsData<'s>,
+        Write<'s, InputHandler<T>>,
+        Read<'s, EventChannel<InputEvent<T>>>,
+        Read<'s, InputReplay>,
+        Write<'s, EventChannel<RebindEvent<T>>>,
+    );
+
+    fn run(
+        &mut self,
+        (
+            mut rebinding, mut ids, mut input, input_events, replay, mut rebind_events
+        ): Self::SystemData
+    ) {
+        // 待っていないときも読み進めておく（古い入力を取り込まないように）
+        let mut captured = None;
+        sync_ids(&mut ids, &input);
+        let reader = self.reader.as_mut().expect("RebindSystem::setup was not called");
+        let events: Vec<&InputEvent<T>> = input_events.read(reader).collect();
+        for event in replay.input_events(&events) {
+            observe_ids(&mut ids, event);
+            if captured.is_some() || !rebinding.is_capturing() {
+                continue;
+            }
+            if is_cancel(event, rebinding.cancel_key) {
+                rebinding.cancel();
+                continue;
+            }
+            captured = rebinding.capture_event(event, &mut ids);
+        }
+
+        if let Some(captured) = captured {
+            let target = rebinding.pending.take().expect("capturing");
+            let found = conflicts(&input.bindings, &target, captured);
+            let stealable = found.iter().all(|c| matches!(c, RebindTarget::Action(_)));
+            let event = if !found.is_empty()
+                && (rebinding.policy == ConflictPolicy::Reject || !stealable)
+            {
+                RebindEvent::Conflict { target, captured, conflicts: found }
+            } else {
+                // 途中で失敗したら割り当てを元に戻す
+                let backup = input.bindings.clone();
+                let result = match captured {
+                    Captured::Button(button) => steal(&mut input.bindings, &found, button),
+                    Captured::ControllerAxis { .. } => Ok(()),
+                };
+                match result.and_then(|()| apply(&mut input.bindings, &target, captured)) {
+                    Ok(()) => {
+                        if let Some(ref path) = rebinding.save_path {
+                            if let Err(e) = save_bindings(&input.bindings, path) {
+                                rebinding.events.push(RebindEvent::SaveFailed(e.to_string()));
+                            }
+                        }
+                        RebindEvent::Bound { target, captured }
+                    }
+                    Err(error) => {
+                        input.bindings = backup;
+                        RebindEvent::Failed { target, captured, error }
+                    }
+                }
+            };
+            rebinding.events.push(event);
+        }
+
+        rebind_events.drain_vec_write(&mut rebinding.events);
+    }
+
+    fn setup(&mut self, res: &mut Resources) {
+        Self::SystemData::setup(res);
+        self.reader = Some(res.fetch_mut::<EventChannel<InputEvent<T>>>().register_reader());
+    }
+}
+
+pub struct RebindBundle<'a, T: BindingTypes = StringBindings> {
+    dep: &'a [&'a str],
+    _marker: PhantomData<T>,
+}
+
+impl<'a, T: BindingTypes> Default for RebindBundle<'a, T> {
+    fn default() -> Self {
+        RebindBundle {
+            dep: &[],
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<'a, T: BindingTypes> RebindBundle<'a, T> {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    pub fn with_dep(mut self, dep: &'a [&'a str]) -> Self {
+        self.dep = dep;
+        self
+    }
+}
+
+impl<'a, 'b, 'c, T: BindingTypes> SystemBundle<'a, 'b> for RebindBundle<'c, T>
+where
+    Bindings<T>: Serialize,
+{
+    fn build(self, builder: &mut DispatcherBuilder<'a, 'b>) -> Result<(), Error> {
+        let dep = {
+            let mut vec = self.dep.to_vec();
+            vec.push("input_system");
+            vec
+        };
+        builder.add(
+            RebindSystem::<T>::new(),
+            "rebind_system",
+            &dep,
+        );
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::replay::{device_id, window_event};
+    use amethyst::{
+        ecs::prelude::Dispatcher,
+        input::InputSystem,
+        winit::{KeyboardInput, ModifiersState},
+    };
+
+    fn setup(policy: ConflictPolicy) -> (World, Dispatcher<'static, 'static>, ReaderId<RebindEvent<StringBindings>>) {
+        let mut world = World::new();
+        let mut dispatcher = DispatcherBuilder::new()
+            .with(InputSystem::<StringBindings>::new(None), "input_system", &[])
+            .with(RebindSystem::<StringBindings>::new(), "rebind_system", &["input_system"])
+            .build();
+        dispatcher.setup(&mut world.res);
+        world.add_resource(ScreenDimensions::new(200, 200, 1.0));
+        world.add_resource(Rebinding::<StringBindings>::new().with_policy(policy));
+        world.write_resource::<InputHandler<StringBindings>>().bindings = bindings();
+        let reader = world
+            .write_resource::<EventChannel<RebindEvent<StringBindings>>>()
+            .register_reader();
+        (world, dispatcher, reader)
+    }
+
+    // jump: Space, fire: Z, horizontal: Right / Left
+    fn bindings() -> Bindings<StringBindings> {
+        let mut bindings = Bindings::new();
+        bindings.insert_action_binding("jump".to_string(), Some(Button::Key(VirtualKeyCode::Space))).unwrap();
+        bindings.insert_action_binding("fire".to_string(), Some(Button::Key(VirtualKeyCode::Z))).unwrap();
+        bindings
+            .insert_axis(
+                "horizontal",
+                Axis::Emulated {
+                    pos: Button::Key(VirtualKeyCode::Right),
+                    neg: Button::Key(VirtualKeyCode::Left),
+                },
+            )
+            .unwrap();
+        bindings
+    }
+
+    // 押して離すまでを 1 フレームで流す
+    fn tap(world: &mut World, dispatcher: &mut Dispatcher, key: VirtualKeyCode) {
+        for &state in [ElementState::Pressed, ElementState::Released].iter() {
+            world.write_resource::<EventChannel<Event>>().single_write(window_event(
+                WindowEvent::KeyboardInput {
+                    device_id: device_id(),
+                    input: KeyboardInput {
+                        scancode: 0,
+                        state,
+                        virtual_keycode: Some(key),
+                        modifiers: ModifiersState::default(),
+                    },
+                },
+            ));
+        }
+        dispatcher.dispatch(&world.res);
+    }
+
+    fn events(world: &World, reader: &mut ReaderId<RebindEvent<StringBindings>>) -> Vec<String> {
+        world
+            .read_resource::<EventChannel<RebindEvent<StringBindings>>>()
+            .read(reader)
+            .map(|event| format!("{:?}", event))
+            .collect()
+    }
+
+    fn combos(world: &World, action: &str) -> Vec<Vec<Button>> {
+        world
+            .read_resource::<InputHandler<StringBindings>>()
+            .bindings
+            .action_bindings(action)
+            .map(|combo| combo.to_vec())
+            .collect()
+    }
+
+    fn capture(world: &World, target: RebindTarget<StringBindings>) {
+        world.write_resource::<Rebinding<StringBindings>>().capture(target);
+    }
+
+    #[test]
+    fn reject_keeps_the_bindings_on_conflict() {
+        let (mut world, mut dispatcher, mut reader) = setup(ConflictPolicy::Reject);
+        capture(&world, RebindTarget::Action("fire".to_string()));
+        tap(&mut world, &mut dispatcher, VirtualKeyCode::Space);
+
+        let received = events(&world, &mut reader);
+        assert_eq!(received.len(), 1);
+        assert!(received[0].starts_with("Conflict"), "{}", received[0]);
+        assert!(received[0].contains("Action(\"jump\")"), "{}", received[0]);
+        assert!(!world.read_resource::<Rebinding<StringBindings>>().is_capturing());
+        assert_eq!(combos(&world, "jump"), vec![vec![Button::Key(VirtualKeyCode::Space)]]);
+        assert_eq!(combos(&world, "fire"), vec![vec![Button::Key(VirtualKeyCode::Z)]]);
+
+        // 競合しなければ既存の割り当てを置き換える
+        capture(&world, RebindTarget::Action("fire".to_string()));
+        tap(&mut world, &mut dispatcher, VirtualKeyCode::X);
+        let received = events(&world, &mut reader);
+        assert!(received[0].starts_with("Bound"), "{}", received[0]);
+        assert_eq!(combos(&world, "fire"), vec![vec![Button::Key(VirtualKeyCode::X)]]);
+    }
+
+    #[test]
+    fn steal_takes_the_button_from_other_actions_only() {
+        let (mut world, mut dispatcher, mut reader) = setup(ConflictPolicy::Steal);
+        capture(&world, RebindTarget::Action("fire".to_string()));
+        tap(&mut world, &mut dispatcher, VirtualKeyCode::Space);
+
+        let received = events(&world, &mut reader);
+        assert_eq!(received.len(), 1);
+        assert!(received[0].starts_with("Bound"), "{}", received[0]);
+        assert!(combos(&world, "jump").is_empty());
+        assert_eq!(combos(&world, "fire"), vec![vec![Button::Key(VirtualKeyCode::Space)]]);
+
+        // 軸からは奪わない
+        capture(&world, RebindTarget::Action("fire".to_string()));
+        tap(&mut world, &mut dispatcher, VirtualKeyCode::Left);
+        let received = events(&world, &mut reader);
+        assert!(received[0].starts_with("Conflict"), "{}", received[0]);
+        assert!(received[0].contains("AxisNegative(\"horizontal\")"), "{}", received[0]);
+        assert_eq!(combos(&world, "fire"), vec![vec![Button::Key(VirtualKeyCode::Space)]]);
+        let input = world.read_resource::<InputHandler<StringBindings>>();
+        assert_eq!(
+            input.bindings.axis("horizontal"),
+            Some(&Axis::Emulated {
+                pos: Button::Key(VirtualKeyCode::Right),
+                neg: Button::Key(VirtualKeyCode::Left),
+            })
+        );
+    }
+
+    #[test]
+    fn cancel_stops_capturing_without_binding() {
+        let (mut world, mut dispatcher, mut reader) = setup(ConflictPolicy::Reject);
+        capture(&world, RebindTarget::Action("fire".to_string()));
+        tap(&mut world, &mut dispatcher, VirtualKeyCode::Escape);
+        let received = events(&world, &mut reader);
+        assert_eq!(received.len(), 1);
+        assert!(received[0].starts_with("Cancelled(Action(\"fire\"))"), "{}", received[0]);
+        assert!(!world.read_resource::<Rebinding<StringBindings>>().is_capturing());
+
+        // 待っていないときのキーは取り込まない
+        tap(&mut world, &mut dispatcher, VirtualKeyCode::X);
+        assert!(events(&world, &mut reader).is_empty());
+        assert_eq!(combos(&world, "fire"), vec![vec![Button::Key(VirtualKeyCode::Z)]]);
+
+        // 別の割り当てを始めると、待っていたものは取り消される
+        capture(&world, RebindTarget::Action("fire".to_string()));
+        capture(&world, RebindTarget::Action("jump".to_string()));
+        world.write_resource::<Rebinding<StringBindings>>().cancel();
+        dispatcher.dispatch(&world.res);
+        let received = events(&world, &mut reader);
+        assert_eq!(received.len(), 2);
+        assert!(received[0].starts_with("Cancelled(Action(\"fire\"))"), "{}", received[0]);
+        assert!(received[1].starts_with("Cancelled(Action(\"jump\"))"), "{}", received[1]);
+        assert_eq!(combos(&world, "jump"), vec![vec![Button::Key(VirtualKeyCode::Space)]]);
+    }
+
+    #[test]
+    fn load_bindings_overrides_defaults_with_the_user_file() {
+        let dir = std::env::temp_dir().join(format!("amethyst_myutils_rebind_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let defaults = dir.join("defaults.ron");
+        let user = dir.join("user.ron");
+        save_bindings(&bindings(), &defaults).unwrap();
+
+        // ユーザーのファイルがなければ既定値のまま
+        let loaded = load_bindings::<StringBindings>(&defaults, &user).unwrap();
+        assert_eq!(loaded.action_bindings("fire").count(), 1);
+
+        // jump は W に変え、Z は dash に使っている
+        let mut custom = Bindings::<StringBindings>::new();
+        custom.insert_action_binding("jump".to_string(), Some(Button::Key(VirtualKeyCode::W))).unwrap();
+        custom.insert_action_binding("dash".to_string(), Some(Button::Key(VirtualKeyCode::Z))).unwrap();
+        save_bindings(&custom, &user).unwrap();
+        let loaded = load_bindings::<StringBindings>(&defaults, &user).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let combos = |action: &str| -> Vec<Vec<Button>> {
+            loaded.action_bindings(action).map(|combo| combo.to_vec()).collect()
+        };
+        assert_eq!(combos("jump"), vec![vec![Button::Key(VirtualKeyCode::W)]]);
+        assert_eq!(combos("dash"), vec![vec![Button::Key(VirtualKeyCode::Z)]]);
+        // 既定値の fire は Z がユーザーの dash と競合するので足さない
+        assert!(combos("fire").is_empty());
+        // ユーザーのファイルにない軸は既定値から足す
+        assert!(loaded.axis("horizontal").is_some());
+    }
+}
@@ -0,0 +1,554 @@
+use super::*;
+use amethyst::{
+    input::InputEvent,
+    winit::{
+        dpi::LogicalPosition,
+        DeviceId, KeyboardInput, ModifiersState, TouchPhase, WindowId
+    },
+};
+use serde::{
+    Deserialize, Serialize
+};
+use std::{
+    fs::File,
+    io::{
+        BufReader, BufWriter
+    },
+    path::Path
+};
+
+// 1 フレーム分の入力（座標はウィンドウ左上原点の物理ピクセル）
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct InputFrame {
+    pub keys: Vec<(VirtualKeyCode, u32)>,
+    pub mouse_buttons: Vec<MouseButton>,
+    pub mouse_position: Option<(f32, f32)>,
+    pub scroll: Vec<MouseScrollDelta>,
+    #[serde(default)]
+    pub characters: Vec<char>,
+    // このフレームの経過時間（秒）。再生中は実際の時間の代わりにこれを使う
+    #[serde(default)]
+    pub delta: f32,
+}
+
+impl InputFrame {
+    pub fn capture<'a, T: BindingTypes>(
+        input: &InputHandler<T>,
+        events: impl IntoIterator<Item = &'a Event>,
+        delta: f32,
+    ) -> Self {
+        let mut scroll = Vec::new();
+        let mut characters = Vec::new();
+        for event in events {
+            if let Some(delta) = scroll_delta(event) {
+                scroll.push(delta);
+            }
+            if let Some(c) = get_received_character(event) {
+                characters.push(c);
+            }
+        }
+        InputFrame {
+            keys: input.keys_that_are_down().zip(input.scan_codes_that_are_down()).collect(),
+            mouse_buttons: input.mouse_buttons_that_are_down().cloned().collect(),
+            mouse_position: input.mouse_position(),
+            scroll,
+            characters,
+            delta,
+        }
+    }
+
+    // InputHandler のキーとマウスをこのフレームの状態に合わせる。
+    // 記録していないコントローラの接続やボタン、軸の状態はそのまま残す
+    pub fn apply<T: BindingTypes>(&self, input: &mut InputHandler<T>) {
+        let current = InputFrame::capture(input, std::iter::empty(), 0.0);
+        let target = InputFrame {
+            keys: self.keys.clone(),
+            mouse_buttons: self.mouse_buttons.clone(),
+            mouse_position: self.mouse_position,
+            ..Default::default()
+        };
+        // 状態の再現だけが目的なので、ここで出る InputEvent は捨てる
+        let mut discard = EventChannel::<InputEvent<T>>::new();
+        for event in target.events(&current) {
+            input.send_event(&event, &mut discard, 1.0);
+        }
+    }
+
+    // previous からこのフレームに移るまでに起きたはずの winit のイベント
+    pub fn events(&self, previous: &InputFrame) -> Vec<Event> {
+        let mut events = Vec::new();
+        let modifiers = modifiers(&self.keys);
+        let key_event = |key, scancode, state| {
+            window_event(WindowEvent::KeyboardInput {
+                device_id: device_id(),
+                input: KeyboardInput {
+                    scancode,
+                    state,
+                    virtual_keycode: Some(key),
+                    modifiers,
+                },
+            })
+        };
+        let button_event = |button, state| {
+            window_event(WindowEvent::MouseInput {
+                device_id: device_id(),
+                state,
+                button,
+                modifiers,
+            })
+        };
+
+        for &(key, scancode) in &previous.keys {
+            if self.keys.iter().all(|(k, _)| *k != key) {
+                events.push(key_event(key, scancode, ElementState::Released));
+            }
+        }
+        for &button in &previous.mouse_buttons {
+            if !self.mouse_buttons.contains(&button) {
+                events.push(button_event(button, ElementState::Released));
+            }
+        }
+        if self.mouse_position != previous.mouse_position {
+            if let Some((x, y)) = self.mouse_position {
+                events.push(window_event(WindowEvent::CursorMoved {
+                    device_id: device_id(),
+                    position: LogicalPosition::new(f64::from(x), f64::from(y)),
+                    modifiers,
+                }));
+            }
+        }
+        for &(key, scancode) in &self.keys {
+            if previous.keys.iter().all(|(k, _)| *k != key) {
+                events.push(key_event(key, scancode, ElementState::Pressed));
+            }
+        }
+        for &button in &self.mouse_buttons {
+            if !previous.mouse_buttons.contains(&button) {
+                events.push(button_event(button, ElementState::Pressed));
+            }
+        }
+        for &delta in &self.scroll {
+            events.push(window_event(WindowEvent::MouseWheel {
+                device_id: device_id(),
+                delta,
+                phase: TouchPhase::Moved,
+                modifiers,
+            }));
+        }
+        for &c in &self.characters {
+            events.push(window_event(WindowEvent::ReceivedCharacter(c)));
+        }
+        events
+    }
+}
+
+fn modifiers(keys: &[(VirtualKeyCode, u32)]) -> ModifiersState {
+    let down = |a, b| keys.iter().any(|(k, _)| *k == a || *k == b);
+    ModifiersState {
+        shift: down(VirtualKeyCode::LShift, VirtualKeyCode::RShift),
+        ctrl: down(VirtualKeyCode::LControl, VirtualKeyCode::RControl),
+        alt: down(VirtualKeyCode::LAlt, VirtualKeyCode::RAlt),
+        logo: down(VirtualKeyCode::LWin, VirtualKeyCode::RWin),
+    }
+}
+
+// ダミーの ID は比較にしか使われず、winit には渡らない
+pub(crate) fn window_event(event: WindowEvent) -> Event {
+    Event::WindowEvent {
+        window_id: unsafe { WindowId::dummy() },
+        event,
+    }
+}
+
+pub(crate) fn device_id() -> DeviceId {
+    unsafe { DeviceId::dummy() }
+}
+
+pub(crate) fn scroll_delta(event: &Event) -> Option<MouseScrollDelta> {
+    match event {
+        Event::WindowEvent { event: WindowEvent::MouseWheel { delta, .. }, .. } => Some(*delta),
+        _ => None,
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct InputRecording {
+    pub frames: Vec<InputFrame>,
+}
+
+impl InputRecording {
+    pub fn save_ron(&self, path: impl AsRef<Path>) -> Result<(), Error> {
+        let text = ron::ser::to_string_pretty(self, Default::default()).map_err(Error::new)?;
+        std::fs::write(path, text).map_err(Error::new)
+    }
+
+    pub fn load_ron(path: impl AsRef<Path>) -> Result<Self, Error> {
+        let file = File::open(path).map_err(Error::new)?;
+        ron::de::from_reader(BufReader::new(file)).map_err(Error::new)
+    }
+
+    pub fn save_bin(&self, path: impl AsRef<Path>) -> Result<(), Error> {
+        let file = File::create(path).map_err(Error::new)?;
+        bincode::serialize_into(BufWriter::new(file), self).map_err(Error::new)
+    }
+
+    pub fn load_bin(path: impl AsRef<Path>) -> Result<Self, Error> {
+        let file = File::open(path).map_err(Error::new)?;
+        bincode::deserialize_from(BufReader::new(file)).map_err(Error::new)
+    }
+}
+
+enum ReplayState {
+    Idle,
+    Recording(InputRecording),
+    Playing(InputRecording, usize),
+}
+
+// 録画と再生の切り替え。再生中は実際の入力の代わりに記録した入力が使われる
+pub struct InputReplay {
+    state: ReplayState,
+    current: Option<InputFrame>,
+    // 再生中のこのフレームのイベントと、記録から作った InputEvent の数
+    events: Vec<Event>,
+    input_events: usize,
+    // 再生中の時刻（記録した経過時間を足していく）
+    time: f64,
+}
+
+impl Default for InputReplay {
+    fn default() -> Self {
+        InputReplay {
+            state: ReplayState::Idle,
+            current: None,
+            events: Vec::new(),
+            input_events: 0,
+            time: 0.0,
+        }
+    }
+}
+
+impl InputReplay {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    pub fn start_recording(&mut self) {
+        self.state = ReplayState::Recording(InputRecording::default());
+    }
+
+    pub fn stop_recording(&mut self) -> Option<InputRecording> {
+        match std::mem::replace(&mut self.state, ReplayState::Idle) {
+            ReplayState::Recording(recording) => Some(recording),
+            state => {
+                self.state = state;
+                None
+            }
+        }
+    }
+
+    pub fn play(&mut self, recording: InputRecording) {
+        self.state = ReplayState::Playing(recording, 0);
+    }
+
+    pub fn stop(&mut self) {
+        self.state = ReplayState::Idle;
+        self.current = None;
+        self.events.clear();
+        self.input_events = 0;
+    }
+
+    pub fn is_recording(&self) -> bool {
+        matches!(self.state, ReplayState::Recording(_))
+    }
+
+    pub fn is_playing(&self) -> bool {
+        matches!(self.state, ReplayState::Playing(..))
+    }
+
+    // 再生中のこのフレームの入力
+    pub fn playing_frame(&self) -> Option<&InputFrame> {
+        self.current.as_ref()
+    }
+
+    // 再生中は記録から作った winit のイベント。EventChannel<Event> の代わりに読む
+    pub fn playing_events(&self) -> Option<&[Event]> {
+        self.current.as_ref().map(|_| self.events.as_slice())
+    }
+
+    // このフレームに EventChannel<InputEvent> から読んだもののうち、使うべきもの。
+    // 再生中は InputReplaySystem が最後に流した記録の分だけを残し、実際の入力の分は捨てる
+    pub fn input_events<'a, E>(&self, events: &'a [E]) -> &'a [E] {
+        match self.current {
+            Some(_) => &events[events.len().saturating_sub(self.input_events)..],
+            None => events,
+        }
+    }
+
+    // 再生中は記録した経過時間で進む時計。クリックやコンボの判定はこちらを使う
+    pub fn delta_seconds(&self, time: &Time) -> f32 {
+        self.current.as_ref().map_or(time.delta_real_seconds(), |frame| frame.delta)
+    }
+
+    pub fn absolute_seconds(&self, time: &Time) -> f64 {
+        match self.current {
+            Some(_) => self.time,
+            None => time.absolute_real_time_seconds(),
+        }
+    }
+}
+
+pub struct InputReplaySystem<T: BindingTypes = StringBindings> {
+    reader: Option<ReaderId<Event>>,
+    _marker: PhantomData<T>,
+}
+
+impl<T: BindingTypes> InputReplaySystem<T> {
+    pub fn new() -> Self {
+        InputReplaySystem {
+            reader: None,
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<T: BindingTypes> Default for InputReplaySystem<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<'s, T: BindingTypes> System<'s> for InputReplaySystem<T> {
+    type SystemData = (
+        Write<'s, InputReplay>,
+        Write<'s, InputHandler<T>>,
+        Read<'s, EventChannel<Event>>,
+        Write<'s, EventChannel<InputEvent<T>>>,
+        Read<'s, Time>,
+    );
+
+    fn run(&mut self, (mut replay, mut input, events, mut input_events, time): Self::SystemData) {
+        let events = events
+            .read(self.reader.as_mut().expect("InputReplaySystem::setup was not called"));
+
+        let replay = &mut *replay;
+        replay.current = None;
+        replay.events.clear();
+        replay.input_events = 0;
+        let finished = match replay.state {
+            ReplayState::Idle => false,
+            ReplayState::Recording(ref mut recording) => {
+                let delta = time.delta_real_seconds();
+                recording.frames.push(InputFrame::capture(&input, events, delta));
+                false
+            }
+            ReplayState::Playing(ref recording, ref mut frame) => {
+                match recording.frames.get(*frame) {
+                    Some(input_frame) => {
+                        let previous = frame
+                            .checked_sub(1)
+                            .and_then(|i| recording.frames.get(i))
+                            .cloned()
+                            .unwrap_or_default();
+                        if *frame == 0 {
+                            replay.time = time.absolute_real_time_seconds();
+                        }
+                        replay.time += f64::from(input_frame.delta);
+                        // 実際の入力で動いた分を前のフレームに戻してから、差分をイベントとして流し直す。
+                        // こうすると KeyPressed や ActionPressed も記録した入力から作られる
+                        previous.apply(&mut input);
+                        replay.events = input_frame.events(&previous);
+                        let mut replayed = EventChannel::<InputEvent<T>>::new();
+                        let mut reader = replayed.register_reader();
+                        for event in &replay.events {
+                            input.send_event(event, &mut replayed, 1.0);
+                        }
+                        let replayed: Vec<InputEvent<T>> =
+                            replayed.read(&mut reader).cloned().collect();
+                        replay.input_events = replayed.len();
+                        input_events.iter_write(replayed);
+                        replay.current = Some(input_frame.clone());
+                        *frame += 1;
+                        false
+                    }
+                    None => true,
+                }
+            }
+        };
+        if finished {
+            replay.stop();
+        }
+    }
+
+    fn setup(&mut self, res: &mut Resources) {
+        Self::SystemData::setup(res);
+        self.reader = Some(res.fetch_mut::<EventChannel<Event>>().register_reader());
+    }
+}
+
+pub struct InputReplayBundle<'a, T: BindingTypes = StringBindings> {
+    dep: &'a [&'a str],
+    _marker: PhantomData<T>,
+}
+
+impl<'a, T: BindingTypes> Default for InputReplayBundle<'a, T> {
+    fn default() -> Self {
+        InputReplayBundle {
+            dep: &[],
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<'a, T: BindingTypes> InputReplayBundle<'a, T> {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    pub fn with_dep(mut self, dep: &'a [&'a str]) -> Self {
+        self.dep = dep;
+        self
+    }
+}
+
+// InputBundle の直後、入力を読む Bundle（MouseBundle や ActionsBundle など）より前に追加すること
+impl<'a, 'b, 'c, T: BindingTypes> SystemBundle<'a, 'b> for InputReplayBundle<'c, T> {
+    fn build(self, builder: &mut DispatcherBuilder<'a, 'b>) -> Result<(), Error> {
+        let dep = {
+            let mut vec = self.dep.to_vec();
+            vec.push("input_system");
+            vec
+        };
+        builder.add(
+            InputReplaySystem::<T>::new(),
+            "input_replay_system",
+            &dep,
+        );
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use amethyst::input::{
+        ControllerButton, ControllerEvent, InputSystem
+    };
+
+    fn key_event(key: VirtualKeyCode) -> Event {
+        window_event(WindowEvent::KeyboardInput {
+            device_id: device_id(),
+            input: KeyboardInput {
+                scancode: 0,
+                state: ElementState::Pressed,
+                virtual_keycode: Some(key),
+                modifiers: ModifiersState::default(),
+            },
+        })
+    }
+
+    #[test]
+    fn playback_replaces_live_input() {
+        let mut world = World::new();
+        let mut dispatcher = DispatcherBuilder::new()
+            .with(InputSystem::<StringBindings>::new(None), "input_system", &[])
+            .with(
+                InputReplaySystem::<StringBindings>::new(),
+                "input_replay_system",
+                &["input_system"],
+            )
+            .build();
+        dispatcher.setup(&mut world.res);
+        world.add_resource(ScreenDimensions::new(200, 100, 1.0));
+        let mut reader = world
+            .write_resource::<EventChannel<InputEvent<StringBindings>>>()
+            .register_reader();
+
+        let pressed = InputFrame {
+            keys: vec![(VirtualKeyCode::A, 0)],
+            characters: vec!['a'],
+            delta: 0.25,
+            ..Default::default()
+        };
+        world.write_resource::<InputReplay>().play(InputRecording {
+            frames: vec![pressed, InputFrame { delta: 0.25, ..Default::default() }],
+        });
+        world.write_resource::<EventChannel<Event>>().single_write(key_event(VirtualKeyCode::B));
+        dispatcher.dispatch(&world.res);
+
+        {
+            let replay = world.read_resource::<InputReplay>();
+            let channel = world.read_resource::<EventChannel<InputEvent<StringBindings>>>();
+            let events: Vec<_> = channel.read(&mut reader).collect();
+            let keys: Vec<VirtualKeyCode> = replay
+                .input_events(&events)
+                .iter()
+                .filter_map(|event| match event {
+                    InputEvent::KeyPressed { key_code, .. } => Some(*key_code),
+                    _ => None,
+                })
+                .collect();
+            assert_eq!(keys, vec![VirtualKeyCode::A]);
+            let characters = replay.playing_frame().map(|frame| frame.characters.clone());
+            assert_eq!(characters, Some(vec!['a']));
+            let input = world.read_resource::<InputHandler<StringBindings>>();
+            assert!(input.key_is_down(VirtualKeyCode::A));
+            assert!(!input.key_is_down(VirtualKeyCode::B));
+        }
+
+        let start = {
+            let time = world.read_resource::<Time>();
+            world.read_resource::<InputReplay>().absolute_seconds(&time)
+        };
+        dispatcher.dispatch(&world.res);
+        let replay = world.read_resource::<InputReplay>();
+        let time = world.read_resource::<Time>();
+        assert_eq!(replay.delta_seconds(&time), 0.25);
+        assert_eq!(replay.absolute_seconds(&time) - start, 0.25);
+        let channel = world.read_resource::<EventChannel<InputEvent<StringBindings>>>();
+        let events: Vec<_> = channel.read(&mut reader).collect();
+        assert!(replay.input_events(&events).iter().any(|event| match event {
+            InputEvent::KeyReleased { key_code, .. } => *key_code == VirtualKeyCode::A,
+            _ => false,
+        }));
+    }
+
+    #[test]
+    fn playback_keeps_connected_controllers() {
+        let mut world = World::new();
+        let mut dispatcher = DispatcherBuilder::new()
+            .with(InputReplaySystem::<StringBindings>::new(), "input_replay_system", &[])
+            .build();
+        dispatcher.setup(&mut world.res);
+        {
+            let mut input = world.write_resource::<InputHandler<StringBindings>>();
+            let mut channel = EventChannel::new();
+            input.send_controller_event(&ControllerEvent::ControllerConnected { which: 3 }, &mut channel);
+            input.send_controller_event(
+                &ControllerEvent::ControllerButtonPressed { which: 3, button: ControllerButton::A },
+                &mut channel,
+            );
+            input.send_event(&key_event(VirtualKeyCode::B), &mut channel, 1.0);
+        }
+
+        let frame = InputFrame {
+            keys: vec![(VirtualKeyCode::A, 0)],
+            mouse_position: Some((10.0, 20.0)),
+            ..Default::default()
+        };
+        world.write_resource::<InputReplay>().play(InputRecording {
+            frames: vec![frame.clone(), frame],
+        });
+        for _ in 0..3 {
+            dispatcher.dispatch(&world.res);
+        }
+
+        assert!(!world.read_resource::<InputReplay>().is_playing());
+        let input = world.read_resource::<InputHandler<StringBindings>>();
+        assert_eq!(input.connected_controllers().collect::<Vec<_>>(), vec![0]);
+        assert!(input.controller_button_is_down(0, ControllerButton::A));
+        // キーとマウスは記録の最後のフレームの状態になる
+        assert!(input.key_is_down(VirtualKeyCode::A));
+        assert!(!input.key_is_down(VirtualKeyCode::B));
+        assert_eq!(input.mouse_position(), Some((10.0, 20.0)));
+    }
+}
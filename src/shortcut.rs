@@ -0,0 +1,436 @@
+use super::*;
+use super::keyboard::Keyboard;
+use super::replay::InputReplay;
+use amethyst::winit::{
+    KeyboardInput, ModifiersState
+};
+use std::{
+    collections::HashSet,
+    fmt,
+    str::FromStr
+};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct Modifiers {
+    pub ctrl: bool,
+    pub shift: bool,
+    pub alt: bool,
+    // Windows キー / Command キー
+    pub logo: bool,
+}
+
+impl From<ModifiersState> for Modifiers {
+    fn from(state: ModifiersState) -> Self {
+        Modifiers {
+            ctrl: state.ctrl,
+            shift: state.shift,
+            alt: state.alt,
+            logo: state.logo,
+        }
+    }
+}
+
+impl Modifiers {
+    // 左右どちらのキーでもよい
+    pub fn from_keys(mut is_down: impl FnMut(VirtualKeyCode) -> bool) -> Self {
+        Modifiers {
+            ctrl: is_down(VirtualKeyCode::LControl) || is_down(VirtualKeyCode::RControl),
+            shift: is_down(VirtualKeyCode::LShift) || is_down(VirtualKeyCode::RShift),
+            alt: is_down(VirtualKeyCode::LAlt) || is_down(VirtualKeyCode::RAlt),
+            logo: is_down(VirtualKeyCode::LWin) || is_down(VirtualKeyCode::RWin),
+        }
+    }
+}
+
+fn is_modifier_key(key: VirtualKeyCode) -> bool {
+    matches!(
+        key,
+        VirtualKeyCode::LControl | VirtualKeyCode::RControl
+            | VirtualKeyCode::LShift | VirtualKeyCode::RShift
+            | VirtualKeyCode::LAlt | VirtualKeyCode::RAlt
+            | VirtualKeyCode::LWin | VirtualKeyCode::RWin
+    )
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ShortcutParseError {
+    Empty,
+    UnknownKey(String),
+    MissingKey,
+    MultipleKeys,
+}
+
+impl fmt::Display for ShortcutParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ShortcutParseError::Empty => write!(f, "shortcut is empty"),
+            ShortcutParseError::UnknownKey(key) => write!(f, "unknown key `{}`", key),
+            ShortcutParseError::MissingKey => write!(f, "shortcut has no non-modifier key"),
+            ShortcutParseError::MultipleKeys => write!(f, "shortcut has more than one non-modifier key"),
+        }
+    }
+}
+
+impl std::error::Error for ShortcutParseError {}
+
+// 修飾キーの組み合わせ + キー 1 つ。修飾キーは完全一致で判定する（Ctrl+S は Ctrl+Shift+S では発火しない）
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Shortcut {
+    pub modifiers: Modifiers,
+    pub key: VirtualKeyCode,
+}
+
+impl Shortcut {
+    pub fn new(key: VirtualKeyCode) -> Self {
+        Shortcut {
+            modifiers: Modifiers::default(),
+            key,
+        }
+    }
+
+    pub fn with_modifiers(mut self, modifiers: Modifiers) -> Self {
+        self.modifiers = modifiers;
+        self
+    }
+
+    // "Ctrl+Shift+S" や "Alt+Enter" の形式。大文字小文字は区別しない
+    pub fn parse(text: &str) -> Result<Self, ShortcutParseError> {
+        if text.trim().is_empty() {
+            return Err(ShortcutParseError::Empty);
+        }
+        let mut modifiers = Modifiers::default();
+        let mut key = None;
+        for part in text.split('+').map(str::trim) {
+            match part.to_lowercase().as_str() {
+                "ctrl" | "control" => modifiers.ctrl = true,
+                "shift" => modifiers.shift = true,
+                "alt" | "option" => modifiers.alt = true,
+                "super" | "win" | "logo" | "cmd" | "command" | "meta" => modifiers.logo = true,
+                _ => {
+                    if key.is_some() {
+                        return Err(ShortcutParseError::MultipleKeys);
+                    }
+                    key = Some(parse_key(part)?);
+                }
+            }
+        }
+        match key {
+            Some(key) => Ok(Shortcut { modifiers, key }),
+            None => Err(ShortcutParseError::MissingKey),
+        }
+    }
+
+    // キーが押された瞬間のイベントか
+    pub fn matches_event(&self, event: &Event) -> bool {
+        match event {
+            Event::WindowEvent {
+                event: WindowEvent::KeyboardInput {
+                    input: KeyboardInput {
+                        state: ElementState::Pressed,
+                        virtual_keycode: Some(key),
+                        modifiers,
+                        ..
+                    },
+                    ..
+                },
+                ..
+            } => *key == self.key && Modifiers::from(*modifiers) == self.modifiers,
+            _ => false,
+        }
+    }
+
+    // 押しっぱなしの間 true
+    pub fn is_down<T: BindingTypes>(&self, input: &InputHandler<T>) -> bool {
+        input.key_is_down(self.key)
+            && Modifiers::from_keys(|key| input.key_is_down(key)) == self.modifiers
+    }
+
+    // 押されたフレームだけ true
+    pub fn is_pressed<T: BindingTypes>(&self, keyboard: &Keyboard<T>) -> bool {
+        keyboard.get_down(self.key)
+            && Modifiers::from_keys(|key| keyboard.get(key)) == self.modifiers
+    }
+}
+
+impl FromStr for Shortcut {
+    type Err = ShortcutParseError;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        Shortcut::parse(text)
+    }
+}
+
+impl fmt::Display for Shortcut {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let names = [
+            (self.modifiers.ctrl, "Ctrl"),
+            (self.modifiers.shift, "Shift"),
+            (self.modifiers.alt, "Alt"),
+            (self.modifiers.logo, "Super"),
+        ];
+        for (_, name) in names.iter().filter(|(on, _)| *on) {
+            write!(f, "{}+", name)?;
+        }
+        match self.key {
+            VirtualKeyCode::Return => write!(f, "Enter"),
+            VirtualKeyCode::Back => write!(f, "Backspace"),
+            key => match format!("{:?}", key) {
+                // Key0 ~ Key9 は数字だけにする
+                ref name if name.len() == 4 && name.starts_with("Key") => write!(f, "{}", &name[3..]),
+                name => write!(f, "{}", name),
+            },
+        }
+    }
+}
+
+fn parse_key(name: &str) -> Result<VirtualKeyCode, ShortcutParseError> {
+    let lower = name.to_lowercase();
+    let key = match lower.as_str() {
+        "enter" | "return" => VirtualKeyCode::Return,
+        "esc" | "escape" => VirtualKeyCode::Escape,
+        "space" => VirtualKeyCode::Space,
+        "tab" => VirtualKeyCode::Tab,
+        "backspace" | "back" => VirtualKeyCode::Back,
+        "del" | "delete" => VirtualKeyCode::Delete,
+        "ins" | "insert" => VirtualKeyCode::Insert,
+        "pgup" | "pageup" => VirtualKeyCode::PageUp,
+        "pgdn" | "pagedown" => VirtualKeyCode::PageDown,
+        "-" => VirtualKeyCode::Minus,
+        "=" => VirtualKeyCode::Equals,
+        "," => VirtualKeyCode::Comma,
+        "." => VirtualKeyCode::Period,
+        "/" => VirtualKeyCode::Slash,
+        _ => {
+            let mut chars = lower.chars();
+            match (chars.next(), chars.next()) {
+                (Some(c), None) if c.is_ascii_digit() => {
+                    return variant(&format!("Key{}", c)).ok_or_else(|| unknown(name));
+                }
+                (Some(c), None) if c.is_ascii_alphabetic() => {
+                    let upper = c.to_ascii_uppercase().to_string();
+                    return variant(&upper).ok_or_else(|| unknown(name));
+                }
+                _ => {}
+            }
+            // それ以外は VirtualKeyCode のバリアント名（先頭だけ大文字にしたものも試す）
+            let capitalized = {
+                let mut chars = lower.chars();
+                match chars.next() {
+                    Some(c) => c.to_ascii_uppercase().to_string() + chars.as_str(),
+                    None => String::new(),
+                }
+            };
+            return variant(name)
+                .or_else(|| variant(&capitalized))
+                .filter(|key| !is_modifier_key(*key))
+                .ok_or_else(|| unknown(name));
+        }
+    };
+    Ok(key)
+}
+
+fn variant(name: &str) -> Option<VirtualKeyCode> {
+    ron::de::from_str(name).ok()
+}
+
+fn unknown(name: &str) -> ShortcutParseError {
+    ShortcutParseError::UnknownKey(name.to_string())
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ShortcutTriggered {
+    pub name: String,
+    pub shortcut: Shortcut,
+}
+
+// 名前付きショートカットの一覧。同じショートカットに複数の名前があればすべて発火する
+#[derive(Debug, Clone, Default)]
+pub struct Shortcuts {
+    shortcuts: Vec<(String, Shortcut)>,
+}
+
+impl Shortcuts {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    pub fn with(mut self, name: impl Into<String>, shortcut: &str) -> Result<Self, ShortcutParseError> {
+        self.bind(name, shortcut)?;
+        Ok(self)
+    }
+
+    // 同じ名前があれば置き換える
+    pub fn insert(&mut self, name: impl Into<String>, shortcut: Shortcut) {
+        let name = name.into();
+        self.remove(&name);
+        self.shortcuts.push((name, shortcut));
+    }
+
+    pub fn bind(&mut self, name: impl Into<String>, shortcut: &str) -> Result<(), ShortcutParseError> {
+        let shortcut = Shortcut::parse(shortcut)?;
+        self.insert(name, shortcut);
+        Ok(())
+    }
+
+    pub fn remove(&mut self, name: &str) -> Option<Shortcut> {
+        let index = self.shortcuts.iter().position(|(n, _)| n == name)?;
+        Some(self.shortcuts.remove(index).1)
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Shortcut> {
+        self.shortcuts.iter().find(|(n, _)| n == name).map(|(_, shortcut)| shortcut)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &Shortcut)> {
+        self.shortcuts.iter().map(|(name, shortcut)| (name.as_str(), shortcut))
+    }
+
+    pub fn matching<'a>(&'a self, event: &'a Event) -> impl Iterator<Item = (&'a str, &'a Shortcut)> {
+        self.iter().filter(move |(_, shortcut)| shortcut.matches_event(event))
+    }
+}
+
+#[derive(Default)]
+pub struct ShortcutSystem {
+    reader: Option<ReaderId<Event>>,
+    // キーリピートで何度も発火しないように押されているキーを覚えておく
+    held: HashSet<VirtualKeyCode>,
+}
+
+impl ShortcutSystem {
+    pub fn new() -> Self {
+        Default::default()
+    }
+}
+
+impl<'s> System<'s> for ShortcutSystem {
+    type SystemData = (
+        Read<'s, Shortcuts>,
+        Read<'s, EventChannel<Event>>,
+        Read<'s, InputReplay>,
+        Write<'s, EventChannel<ShortcutTriggered>>,
+    );
+
+    fn run(&mut self, (shortcuts, events, replay, mut triggered): Self::SystemData) {
+        let reader = self.reader.as_mut().expect("ShortcutSystem::setup was not called");
+        let live = events.read(reader);
+        // 再生中は実際のキー入力の代わりに記録したものを使う
+        let events: Vec<&Event> = match replay.playing_events() {
+            Some(replayed) => replayed.iter().collect(),
+            None => live.collect(),
+        };
+        for event in events {
+            if let Event::WindowEvent {
+                event: WindowEvent::KeyboardInput {
+                    input: KeyboardInput { state, virtual_keycode: Some(key), .. },
+                    ..
+                },
+                ..
+            } = event
+            {
+                let repeated = match state {
+                    ElementState::Pressed => !self.held.insert(*key),
+                    ElementState::Released => {
+                        self.held.remove(key);
+                        continue;
+                    }
+                };
+                if repeated {
+                    continue;
+                }
+                for (name, shortcut) in shortcuts.matching(event) {
+                    triggered.single_write(ShortcutTriggered {
+                        name: name.to_string(),
+                        shortcut: *shortcut,
+                    });
+                }
+            }
+        }
+    }
+
+    fn setup(&mut self, res: &mut Resources) {
+        Self::SystemData::setup(res);
+        self.reader = Some(res.fetch_mut::<EventChannel<Event>>().register_reader());
+    }
+}
+
+#[derive(Default)]
+pub struct ShortcutBundle<'a> {
+    dep: &'a [&'a str]
+}
+
+impl<'a> ShortcutBundle<'a> {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    pub fn with_dep(mut self, dep: &'a [&'a str]) -> Self {
+        self.dep = dep;
+        self
+    }
+}
+
+impl<'a, 'b, 'c> SystemBundle<'a, 'b> for ShortcutBundle<'c> {
+    fn build(self, builder: &mut DispatcherBuilder<'a, 'b>) -> Result<(), Error> {
+        builder.add(
+            ShortcutSystem::new(),
+            "shortcut_system",
+            self.dep,
+        );
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shortcut_round_trips_through_display() {
+        let keys = [
+            VirtualKeyCode::S, VirtualKeyCode::Key1, VirtualKeyCode::F5, VirtualKeyCode::Return,
+            VirtualKeyCode::Back, VirtualKeyCode::Escape, VirtualKeyCode::PageUp,
+            VirtualKeyCode::Minus, VirtualKeyCode::Numpad1, VirtualKeyCode::Left,
+        ];
+        for &key in keys.iter() {
+            for bits in 0..16 {
+                let shortcut = Shortcut::new(key).with_modifiers(Modifiers {
+                    ctrl: bits & 1 != 0,
+                    shift: bits & 2 != 0,
+                    alt: bits & 4 != 0,
+                    logo: bits & 8 != 0,
+                });
+                let text = shortcut.to_string();
+                assert_eq!(Shortcut::parse(&text), Ok(shortcut), "{}", text);
+                assert_eq!(text.parse::<Shortcut>(), Ok(shortcut));
+            }
+        }
+
+        assert_eq!(
+            Shortcut::new(VirtualKeyCode::S)
+                .with_modifiers(Modifiers { ctrl: true, shift: true, ..Default::default() })
+                .to_string(),
+            "Ctrl+Shift+S"
+        );
+        assert_eq!(Shortcut::new(VirtualKeyCode::Key0).to_string(), "0");
+        // 表記ゆれは同じショートカットになる
+        assert_eq!(Shortcut::parse(" control + alt + return "), Shortcut::parse("Ctrl+Alt+Enter"));
+        assert_eq!(Shortcut::parse("cmd+s"), Shortcut::parse("Super+S"));
+    }
+
+    #[test]
+    fn shortcut_parse_errors() {
+        assert_eq!(Shortcut::parse("  "), Err(ShortcutParseError::Empty));
+        assert_eq!(Shortcut::parse("Ctrl+Shift"), Err(ShortcutParseError::MissingKey));
+        assert_eq!(Shortcut::parse("Ctrl+A+B"), Err(ShortcutParseError::MultipleKeys));
+        assert_eq!(
+            Shortcut::parse("Ctrl+Nope"),
+            Err(ShortcutParseError::UnknownKey("Nope".to_string()))
+        );
+        // 修飾キーそのものはキーとして使えない
+        assert_eq!(
+            Shortcut::parse("Ctrl+LShift"),
+            Err(ShortcutParseError::UnknownKey("LShift".to_string()))
+        );
+    }
+}
@@ -0,0 +1,360 @@
+use super::*;
+use super::replay::InputReplay;
+
+const BACKSPACE: char = '\u{8}';
+// macOS では Backspace で DEL が送られてくる
+const DELETE: char = '\u{7f}';
+
+#[derive(Debug, Clone, Default)]
+pub struct TextInput {
+    text: String,
+    // IME で変換中の文字列（未確定）
+    composition: String,
+    focused: bool,
+    max_length: Option<usize>,
+    clear_on_submit: bool,
+
+    // このフレームの入力
+    typed: String,
+    backspaces: usize,
+    submitted: Option<String>,
+}
+
+impl TextInput {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    // 文字数（char 単位）の上限
+    pub fn with_max_length(mut self, max_length: usize) -> Self {
+        self.max_length = Some(max_length);
+        self
+    }
+
+    // Enter で確定したあと空にする（チャット欄向け）
+    pub fn with_clear_on_submit(mut self, clear_on_submit: bool) -> Self {
+        self.clear_on_submit = clear_on_submit;
+        self
+    }
+
+    pub fn focus(&mut self) {
+        self.focused = true;
+    }
+
+    // 変換中の文字列は捨てる
+    pub fn unfocus(&mut self) {
+        self.focused = false;
+        self.composition.clear();
+    }
+
+    pub fn is_focused(&self) -> bool {
+        self.focused
+    }
+
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    pub fn set_text(&mut self, text: impl Into<String>) {
+        self.text = text.into();
+        self.truncate();
+    }
+
+    pub fn clear(&mut self) {
+        self.text.clear();
+        self.composition.clear();
+    }
+
+    pub fn composition(&self) -> &str {
+        &self.composition
+    }
+
+    pub fn is_composing(&self) -> bool {
+        !self.composition.is_empty()
+    }
+
+    // 表示用（確定済み + 変換中）
+    pub fn display_text(&self) -> String {
+        format!("{}{}", self.text, self.composition)
+    }
+
+    // winit 0.19 は変換中の文字列をイベントで送ってこないので、取得できる環境ではここから渡す。
+    // 確定した文字列は ReceivedCharacter で届くので、確定時は空文字列を渡せばよい
+    pub fn set_composition(&mut self, composition: impl Into<String>) {
+        if self.focused {
+            self.composition = composition.into();
+        }
+    }
+
+    // 変換中の文字列をそのまま確定する
+    pub fn commit_composition(&mut self) {
+        let composition = std::mem::take(&mut self.composition);
+        for c in composition.chars() {
+            self.push(c);
+        }
+    }
+
+    // このフレームに入力された文字
+    pub fn typed(&self) -> &str {
+        &self.typed
+    }
+
+    pub fn backspaces(&self) -> usize {
+        self.backspaces
+    }
+
+    // このフレームに Enter で確定された文字列
+    pub fn submitted(&self) -> Option<&str> {
+        self.submitted.as_deref()
+    }
+
+    fn begin_frame(&mut self) {
+        self.typed.clear();
+        self.backspaces = 0;
+        self.submitted = None;
+    }
+
+    fn receive(&mut self, c: char) {
+        if !self.focused {
+            return;
+        }
+        match c {
+            BACKSPACE | DELETE => {
+                // 変換中なら変換中の文字列から消す
+                if self.composition.pop().is_none() && self.text.pop().is_some() {
+                    self.backspaces += 1;
+                }
+            }
+            '\r' | '\n' => {
+                // 変換中の Enter は IME の確定なので送信しない
+                if self.is_composing() {
+                    self.commit_composition();
+                    return;
+                }
+                self.submitted = Some(self.text.clone());
+                if self.clear_on_submit {
+                    self.text.clear();
+                }
+            }
+            c if c.is_control() => {}
+            c => self.push(c),
+        }
+    }
+
+    fn push(&mut self, c: char) {
+        if self.max_length.map_or(false, |max| self.text.chars().count() >= max) {
+            return;
+        }
+        self.text.push(c);
+        self.typed.push(c);
+    }
+
+    fn truncate(&mut self) {
+        if let Some(max) = self.max_length {
+            if let Some((index, _)) = self.text.char_indices().nth(max) {
+                self.text.truncate(index);
+            }
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct TextInputSystem {
+    reader: Option<ReaderId<Event>>,
+}
+
+impl TextInputSystem {
+    pub fn new() -> Self {
+        Default::default()
+    }
+}
+
+impl<'s> System<'s> for TextInputSystem {
+    type SystemData = (
+        Write<'s, TextInput>,
+        Read<'s, EventChannel<Event>>,
+        Read<'s, InputReplay>,
+    );
+
+    fn run(&mut self, (mut text_input, events, replay): Self::SystemData) {
+        text_input.begin_frame();
+        let reader = self.reader.as_mut().expect("TextInputSystem::setup was not called");
+        let live = events.read(reader);
+        // 再生中は記録した文字だけを受け取る
+        let characters: Vec<char> = match replay.playing_frame() {
+            Some(frame) => frame.characters.clone(),
+            None => live.filter_map(get_received_character).collect(),
+        };
+        for c in characters {
+            text_input.receive(c);
+        }
+    }
+
+    fn setup(&mut self, res: &mut Resources) {
+        Self::SystemData::setup(res);
+        self.reader = Some(res.fetch_mut::<EventChannel<Event>>().register_reader());
+    }
+}
+
+#[derive(Default)]
+pub struct TextInputBundle<'a> {
+    dep: &'a [&'a str]
+}
+
+impl<'a> TextInputBundle<'a> {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    pub fn with_dep(mut self, dep: &'a [&'a str]) -> Self {
+        self.dep = dep;
+        self
+    }
+}
+
+impl<'a, 'b, 'c> SystemBundle<'a, 'b> for TextInputBundle<'c> {
+    fn build(self, builder: &mut DispatcherBuilder<'a, 'b>) -> Result<(), Error> {
+        builder.add(
+            TextInputSystem::new(),
+            "text_input_system",
+            self.dep,
+        );
+        Ok(())
+    }
+}
+
+pub fn initialise_text_input(world: &mut World) {
+    world.add_resource(TextInput::new());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::replay::window_event;
+    use amethyst::ecs::prelude::Dispatcher;
+
+    fn setup(text_input: TextInput) -> (World, Dispatcher<'static, 'static>) {
+        let mut world = World::new();
+        let mut dispatcher = DispatcherBuilder::new()
+            .with(TextInputSystem::new(), "text_input_system", &[])
+            .build();
+        dispatcher.setup(&mut world.res);
+        world.add_resource(text_input);
+        (world, dispatcher)
+    }
+
+    // 1 フレームで文字を流す
+    fn type_chars(world: &mut World, dispatcher: &mut Dispatcher, text: &str) {
+        for c in text.chars() {
+            world
+                .write_resource::<EventChannel<Event>>()
+                .single_write(window_event(WindowEvent::ReceivedCharacter(c)));
+        }
+        dispatcher.dispatch(&world.res);
+    }
+
+    fn focused() -> TextInput {
+        let mut text_input = TextInput::new();
+        text_input.focus();
+        text_input
+    }
+
+    #[test]
+    fn backspace_and_delete_remove_whole_characters() {
+        let (mut world, mut dispatcher) = setup(focused());
+        type_chars(&mut world, &mut dispatcher, "aあ🎮");
+        {
+            let text_input = world.read_resource::<TextInput>();
+            assert_eq!(text_input.text(), "aあ🎮");
+            assert_eq!(text_input.typed(), "aあ🎮");
+        }
+
+        type_chars(&mut world, &mut dispatcher, &BACKSPACE.to_string());
+        assert_eq!(world.read_resource::<TextInput>().text(), "aあ");
+        type_chars(&mut world, &mut dispatcher, &format!("{}{}{}", DELETE, DELETE, DELETE));
+        let text_input = world.read_resource::<TextInput>();
+        assert_eq!(text_input.text(), "");
+        // 空になってからの分は数えない
+        assert_eq!(text_input.backspaces(), 2);
+        assert_eq!(text_input.typed(), "");
+    }
+
+    #[test]
+    fn enter_submits_and_clears() {
+        let (mut world, mut dispatcher) = setup(focused().with_clear_on_submit(true));
+        type_chars(&mut world, &mut dispatcher, "こんにちは\r");
+        {
+            let text_input = world.read_resource::<TextInput>();
+            assert_eq!(text_input.submitted(), Some("こんにちは"));
+            assert_eq!(text_input.text(), "");
+        }
+
+        // 確定した文字列はそのフレームだけ
+        dispatcher.dispatch(&world.res);
+        assert_eq!(world.read_resource::<TextInput>().submitted(), None);
+
+        // clear_on_submit でなければ残す
+        let (mut world, mut dispatcher) = setup(focused());
+        type_chars(&mut world, &mut dispatcher, "hi\n");
+        let text_input = world.read_resource::<TextInput>();
+        assert_eq!(text_input.submitted(), Some("hi"));
+        assert_eq!(text_input.text(), "hi");
+    }
+
+    #[test]
+    fn input_is_ignored_without_focus() {
+        let (mut world, mut dispatcher) = setup(TextInput::new());
+        type_chars(&mut world, &mut dispatcher, "abc\r");
+        {
+            let mut text_input = world.write_resource::<TextInput>();
+            assert_eq!(text_input.text(), "");
+            assert_eq!(text_input.typed(), "");
+            assert_eq!(text_input.submitted(), None);
+            text_input.set_composition("か");
+            assert!(!text_input.is_composing());
+            text_input.focus();
+        }
+
+        type_chars(&mut world, &mut dispatcher, "ab");
+        world.write_resource::<TextInput>().unfocus();
+        type_chars(&mut world, &mut dispatcher, &format!("c{}", BACKSPACE));
+        assert_eq!(world.read_resource::<TextInput>().text(), "ab");
+    }
+
+    #[test]
+    fn composition_is_not_part_of_the_committed_text() {
+        let (mut world, mut dispatcher) = setup(focused().with_clear_on_submit(true));
+        type_chars(&mut world, &mut dispatcher, "a");
+        world.write_resource::<TextInput>().set_composition("にほん");
+        {
+            let text_input = world.read_resource::<TextInput>();
+            assert_eq!(text_input.text(), "a");
+            assert_eq!(text_input.display_text(), "aにほん");
+        }
+
+        // 変換中の Backspace は変換中の文字列から消す
+        type_chars(&mut world, &mut dispatcher, &BACKSPACE.to_string());
+        {
+            let text_input = world.read_resource::<TextInput>();
+            assert_eq!(text_input.text(), "a");
+            assert_eq!(text_input.composition(), "にほ");
+            assert_eq!(text_input.backspaces(), 0);
+        }
+
+        // 変換中の Enter は確定だけで送信しない
+        type_chars(&mut world, &mut dispatcher, "\r");
+        {
+            let text_input = world.read_resource::<TextInput>();
+            assert_eq!(text_input.submitted(), None);
+            assert_eq!(text_input.text(), "aにほ");
+            assert!(!text_input.is_composing());
+        }
+
+        // フォーカスを外すと変換中の文字列は捨てる
+        let mut text_input = world.write_resource::<TextInput>();
+        text_input.set_composition("ご");
+        text_input.unfocus();
+        assert_eq!(text_input.text(), "aにほ");
+        assert_eq!(text_input.display_text(), "aにほ");
+    }
+}
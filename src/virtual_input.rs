@@ -0,0 +1,228 @@
+use super::*;
+use super::replay::{
+    device_id, window_event
+};
+use amethyst::winit::{
+    dpi::LogicalPosition,
+    KeyboardInput, ModifiersState, TouchPhase
+};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum VirtualEvent {
+    Key(VirtualKeyCode, u32, ElementState),
+    Button(MouseButton, ElementState),
+    // 物理ピクセル
+    Move(f32, f32),
+    Scroll(MouseScrollDelta),
+}
+
+// テスト用の入力キュー。フレーム番号を指定して積むと、そのフレームに winit のイベントとして流れる
+#[derive(Default)]
+pub struct VirtualInput {
+    frame: u64,
+    queue: Vec<(u64, VirtualEvent)>,
+}
+
+impl VirtualInput {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    // 次に処理されるフレーム番号
+    pub fn frame(&self) -> u64 {
+        self.frame
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.queue.is_empty()
+    }
+
+    // scancode は 0（どの物理キーでもない）として送る。
+    // 位置で判定するコードを試すときは press_scancode で実際の値を指定する
+    pub fn press_key(&mut self, frame: u64, key: VirtualKeyCode) -> &mut Self {
+        self.press_scancode(frame, key, 0)
+    }
+
+    pub fn release_key(&mut self, frame: u64, key: VirtualKeyCode) -> &mut Self {
+        self.release_scancode(frame, key, 0)
+    }
+
+    pub fn press_scancode(&mut self, frame: u64, key: VirtualKeyCode, scancode: u32) -> &mut Self {
+        self.push(frame, VirtualEvent::Key(key, scancode, ElementState::Pressed))
+    }
+
+    pub fn release_scancode(&mut self, frame: u64, key: VirtualKeyCode, scancode: u32) -> &mut Self {
+        self.push(frame, VirtualEvent::Key(key, scancode, ElementState::Released))
+    }
+
+    // frame で押して次のフレームで離す
+    pub fn tap_key(&mut self, frame: u64, key: VirtualKeyCode) -> &mut Self {
+        self.press_key(frame, key).release_key(frame + 1, key)
+    }
+
+    pub fn press_button(&mut self, frame: u64, button: MouseButton) -> &mut Self {
+        self.push(frame, VirtualEvent::Button(button, ElementState::Pressed))
+    }
+
+    pub fn release_button(&mut self, frame: u64, button: MouseButton) -> &mut Self {
+        self.push(frame, VirtualEvent::Button(button, ElementState::Released))
+    }
+
+    pub fn click(&mut self, frame: u64, button: MouseButton) -> &mut Self {
+        self.press_button(frame, button).release_button(frame + 1, button)
+    }
+
+    // ウィンドウ左上原点の物理ピクセル
+    pub fn move_mouse(&mut self, frame: u64, x: f32, y: f32) -> &mut Self {
+        self.push(frame, VirtualEvent::Move(x, y))
+    }
+
+    pub fn scroll(&mut self, frame: u64, x: f32, y: f32) -> &mut Self {
+        self.push(frame, VirtualEvent::Scroll(MouseScrollDelta::LineDelta(x, y)))
+    }
+
+    pub fn scroll_pixels(&mut self, frame: u64, x: f32, y: f32) -> &mut Self {
+        let position = LogicalPosition::new(f64::from(x), f64::from(y));
+        self.push(frame, VirtualEvent::Scroll(MouseScrollDelta::PixelDelta(position)))
+    }
+
+    fn push(&mut self, frame: u64, event: VirtualEvent) -> &mut Self {
+        self.queue.push((frame, event));
+        self
+    }
+
+    // このフレームの分を積んだ順に取り出す（過ぎたフレームの分もここで流す）
+    fn take_due(&mut self) -> Vec<VirtualEvent> {
+        let frame = self.frame;
+        let (due, rest): (Vec<_>, Vec<_>) = self.queue.drain(..).partition(|(f, _)| *f <= frame);
+        self.queue = rest;
+        self.frame += 1;
+        due.into_iter().map(|(_, event)| event).collect()
+    }
+}
+
+fn to_event(event: VirtualEvent, hidpi: f64) -> Event {
+    let modifiers = ModifiersState::default();
+    window_event(match event {
+        VirtualEvent::Key(key, scancode, state) => WindowEvent::KeyboardInput {
+            device_id: device_id(),
+            input: KeyboardInput {
+                scancode,
+                state,
+                virtual_keycode: Some(key),
+                modifiers,
+            },
+        },
+        VirtualEvent::Button(button, state) => WindowEvent::MouseInput {
+            device_id: device_id(),
+            state,
+            button,
+            modifiers,
+        },
+        // InputHandler が HiDPI 係数を掛けるので論理ピクセルに戻しておく
+        VirtualEvent::Move(x, y) => WindowEvent::CursorMoved {
+            device_id: device_id(),
+            position: LogicalPosition::new(f64::from(x) / hidpi, f64::from(y) / hidpi),
+            modifiers,
+        },
+        VirtualEvent::Scroll(delta) => WindowEvent::MouseWheel {
+            device_id: device_id(),
+            delta,
+            phase: TouchPhase::Moved,
+            modifiers,
+        },
+    })
+}
+
+pub struct VirtualInputSystem;
+
+impl<'s> System<'s> for VirtualInputSystem {
+    type SystemData = (
+        Write<'s, VirtualInput>,
+        Write<'s, EventChannel<Event>>,
+        Option<Read<'s, ScreenDimensions>>,
+    );
+
+    fn run(&mut self, (mut virtual_input, mut events, screen): Self::SystemData) {
+        let hidpi = screen.map_or(1.0, |screen| screen.hidpi_factor());
+        for event in virtual_input.take_due() {
+            events.single_write(to_event(event, hidpi));
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct VirtualInputBundle<'a> {
+    dep: &'a [&'a str]
+}
+
+impl<'a> VirtualInputBundle<'a> {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    pub fn with_dep(mut self, dep: &'a [&'a str]) -> Self {
+        self.dep = dep;
+        self
+    }
+}
+
+// InputBundle より前に追加すること（同じフレームのうちに input_system が読む）
+impl<'a, 'b, 'c> SystemBundle<'a, 'b> for VirtualInputBundle<'c> {
+    fn build(self, builder: &mut DispatcherBuilder<'a, 'b>) -> Result<(), Error> {
+        builder.add(
+            VirtualInputSystem,
+            "virtual_input_system",
+            self.dep,
+        );
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::mouse::{
+        Mouse, MouseSystem
+    };
+    use amethyst::input::InputSystem;
+
+    #[test]
+    fn mouse_follows_virtual_input() {
+        let mut world = World::new();
+        let mut dispatcher = DispatcherBuilder::new()
+            .with(VirtualInputSystem, "virtual_input_system", &[])
+            .with(
+                InputSystem::<StringBindings>::new(None),
+                "input_system",
+                &["virtual_input_system"],
+            )
+            .with(MouseSystem::<StringBindings>::new(), "mouse_system", &["input_system"])
+            .build();
+        dispatcher.setup(&mut world.res);
+        // InputSystem が読むのでウィンドウの代わりに入れておく。MouseSystem は無くても動く
+        world.add_resource(ScreenDimensions::new(200, 100, 1.0));
+
+        world.write_resource::<VirtualInput>()
+            .move_mouse(0, 30.0, 40.0)
+            .press_button(0, MouseButton::Left)
+            .release_button(1, MouseButton::Left)
+            .tap_key(1, VirtualKeyCode::A);
+
+        dispatcher.dispatch(&world.res);
+        {
+            let mouse = world.read_resource::<Mouse>();
+            assert!(mouse.get_down(MouseButton::Left));
+            assert_eq!(mouse.screen_position(), (30.0, 40.0));
+            // カメラがなければ左下原点
+            assert_eq!((mouse.x, mouse.y), (30.0, 60.0));
+        }
+
+        dispatcher.dispatch(&world.res);
+        let mouse = world.read_resource::<Mouse>();
+        assert!(mouse.get_up(MouseButton::Left));
+        assert_eq!(mouse.click_count(MouseButton::Left), 1);
+        let input = world.read_resource::<InputHandler<StringBindings>>();
+        assert!(input.key_is_down(VirtualKeyCode::A));
+    }
+}